@@ -5,9 +5,7 @@ use bevy::{
 };
 use glam::{Vec3, Vec3A};
 use ilattice::prelude::Extent;
-use octree_dual_contour::{
-    central_gradient, repair_sharp_normals, sdf_primitives::*, CellOctree, MeshVertexId,
-};
+use octree_dual_contour::{sdf_primitives::*, CellOctree, IsoMesh, MeshOptions};
 use smooth_bevy_cameras::{controllers::fps::*, LookTransformPlugin};
 use std::time::Instant;
 
@@ -86,43 +84,24 @@ fn spawn_scene(
     let mut octree =
         CellOctree::build(root_cell, max_depth, error_tolerance, precision, field).unwrap();
     println!("octree build took {} us", build_t0.elapsed().as_micros());
-    let mut min_leaf_depth = u8::MAX;
-    let mut max_leaf_depth = 0;
-    let mut positions: Vec<Vec3A> = Vec::new();
-    let mut normals = Vec::new();
-    let mut quad_indices = Vec::new();
-    let mut tri_indices = Vec::new();
-    let contour_t0 = Instant::now();
-    octree.dual_contour(
-        |_cell_id, cell| {
-            min_leaf_depth = min_leaf_depth.min(cell.depth);
-            max_leaf_depth = max_leaf_depth.max(cell.depth);
-
-            cell.mesh_vertex_id = positions.len() as MeshVertexId;
-            positions.push(cell.vertex_estimate.into());
-            normals.push(central_gradient(&field, cell.vertex_estimate.into(), 0.001).normalize());
-        },
-        |q| {
-            quad_indices.extend_from_slice(&[q[0], q[2], q[1], q[1], q[2], q[3]]);
-        },
-        |tri| {
-            tri_indices.extend_from_slice(&tri);
-        },
+    let stats = octree.stats();
+    println!(
+        "# cells = {}, {} bytes, {:?} SDF evaluations",
+        stats.total_cells(),
+        stats.total_bytes(),
+        stats.sdf_evaluations
     );
+    if let Some(depths) = stats.leaf_depth_range() {
+        println!("leaf depth = {depths:?}");
+    }
+    let contour_t0 = Instant::now();
+    let IsoMesh {
+        positions,
+        normals,
+        tri_indices,
+        ..
+    } = octree.contour_to_mesh(&MeshOptions::default(), field);
     println!("dual contour took {} us", contour_t0.elapsed().as_micros());
-    println!("depth = {min_leaf_depth}..={max_leaf_depth}");
-    tri_indices.append(&mut quad_indices);
-
-    // Now we need to create the mesh by copying the proper vertices out of the
-    // octree. Since not all vertices will be used, we need to recreate the
-    // vertex IDs based on the new mesh.
-    let all_cells = octree.all_cells();
-    let mut tri_indices: Vec<_> = tri_indices
-        .into_iter()
-        .map(|i| all_cells[i as usize].mesh_vertex_id)
-        .collect();
-
-    repair_sharp_normals(0.95, &mut tri_indices, &mut positions, &mut normals);
 
     println!("# isosurface vertices = {}", positions.len());
     println!("# isosurface triangles = {}", tri_indices.len() / 3);
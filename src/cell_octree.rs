@@ -1,38 +1,249 @@
 use crate::{
-    branch_empty_check, cell_is_bipolar, estimate_interior_vertex_qef,
-    mesh::{MeshVertexId, NULL_MESH_VERTEX_ID},
-    qef::Qef,
+    branch_empty_check, cell_is_bipolar, estimate_interior_vertex_qef, qef::Qef, sdf::root_cap,
+    MaterialId, SharpFeature,
 };
 use glam::{Vec3, Vec3A};
 use ilattice::extent::Extent;
+use std::{cmp::Ordering, collections::BinaryHeap};
 
 #[derive(Debug, Default)]
 pub struct CellOctree {
     pub(crate) root_id: CellId,
 
     pub(crate) all_cells: Vec<Cell>,
+    /// Empty unless [`BuildOptions::store_qefs`] is set. Otherwise parallel to
+    /// `all_cells`.
+    pub(crate) qefs: Vec<CellQefs>,
+    /// Empty unless built by [`Self::build_multi_material`]. Otherwise parallel
+    /// to `all_cells`.
+    pub(crate) corner_materials: Vec<[MaterialId; 8]>,
+    /// At least the farthest that any leaf vertex lies outside of its cell.
+    /// Raised whenever a contouring visitor may have moved a vertex.
+    pub(crate) vertex_escape: f32,
+    /// Subtracted from all samples. See [`BuildOptions::iso_value`].
+    pub(crate) iso_value: f32,
+    /// The inset of the cap if built with [`BuildOptions::cap_to_root`].
+    pub(crate) cap_inset: Option<f32>,
+    /// `None` if this octree was loaded rather than built.
+    pub(crate) build_report: Option<BuildReport>,
     pub(crate) cell_stack: Vec<CellId>,
-    pub(crate) face_stack: Vec<Face>,
-    pub(crate) edge_stack: Vec<Edge>,
+    pub(crate) work_stack: Vec<ContourWork>,
+}
+
+/// Parameters for [`CellOctree::build_with_options`].
+#[derive(Clone, Debug)]
+pub struct BuildOptions {
+    pub max_depth: u8,
+    /// Branches are simplified into pseudo-leaves if their QEF error is at most
+    /// this value.
+    pub error_tolerance: f32,
+    /// Standard deviation of the probabilistic QEF normals, relative to the
+    /// cell size for positions.
+    pub precision: f32,
+    /// Keep the QEFs of all cells with vertices. See [`CellOctree::qefs`].
+    pub store_qefs: bool,
+    /// Run [`CellOctree::compact`] after building.
+    pub compact: bool,
+    /// Keep `all_cells` from growing beyond this many cells.
+    ///
+    /// When the budget is reached, the finished branches with the smallest
+    /// QEF error are collapsed into pseudo-leaves, and the cells below them
+    /// removed, to make room for the rest of the build. So the budget goes to
+    /// the regions with the largest error, wherever they are built. Only if
+    /// that frees too few cells does the build stop refining, as if by
+    /// [`BuildControl::StopRefining`]. Either way,
+    /// [`BuildReport::budget_exhausted`] is set.
+    ///
+    /// Collapsing also removes the cells below other pseudo-leaves, like
+    /// [`CellOctree::compact`]. QEFs are kept while building to measure the
+    /// error, even without [`Self::store_qefs`].
+    ///
+    /// At least 9 cells are always allowed. Use [`Self::bytes_per_cell`] to
+    /// convert from a byte budget.
+    pub max_cells: Option<usize>,
+    /// The level set of the SDF to build and contour.
+    pub iso_value: f32,
+    /// Other level sets that the octree should resolve, to be extracted with
+    /// [`CellOctree::contour_shells`]. Cells that these cross are never
+    /// simplified.
+    ///
+    /// [`CellOctree::dual_contour`] also visits the leaves that only a shell
+    /// crosses, though no polygons of the octree's own surface use them.
+    pub shell_iso_values: Vec<f32>,
+    /// Close the surface where the solid extends past the root cell, by
+    /// intersecting the solid with the root cell shrunk by half of the
+    /// smallest cell size.
+    ///
+    /// The mesh methods cap the SDF the same way for normals (see
+    /// [`CellOctree::surface_sdf`]), so caps are flat. Shells are not capped.
+    pub cap_to_root: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 7,
+            error_tolerance: 0.00001,
+            precision: 0.1,
+            store_qefs: false,
+            compact: false,
+            max_cells: None,
+            iso_value: 0.0,
+            shell_iso_values: Vec::new(),
+            cap_to_root: false,
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Bytes allocated per cell in [`CellOctree::all_cells`] and
+    /// [`CellOctree::qefs`] while building, which includes QEFs if
+    /// [`Self::max_cells`] is set.
+    pub fn bytes_per_cell(&self) -> usize {
+        let qef_bytes = if self.store_qefs || self.max_cells.is_some() {
+            std::mem::size_of::<CellQefs>()
+        } else {
+            0
+        };
+        std::mem::size_of::<Cell>() + qef_bytes
+    }
+}
+
+/// What happened during [`CellOctree::build_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct BuildReport {
+    pub sdf_evaluations: u64,
+    /// The build was asked to stop refining by [`BuildControl::StopRefining`],
+    /// so the octree may be coarser than requested.
+    pub stopped_refining: bool,
+    /// [`BuildOptions::max_cells`] was reached, so the octree may be coarser
+    /// than requested.
+    pub budget_exhausted: bool,
+}
+
+/// Passed to the callback of [`CellOctree::build_with_progress`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BuildProgress {
+    /// Fraction of the root cell's volume that has been fully built, in
+    /// `[0, 1]`.
+    pub fraction: f32,
+    /// Number of cells created so far, less those removed to fit
+    /// [`BuildOptions::max_cells`].
+    pub cells: usize,
+}
+
+/// Returned by the callback of [`CellOctree::build_with_progress`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BuildControl {
+    #[default]
+    Continue,
+    /// Finish the build without subdividing any more cells. Unvisited branches
+    /// that may contain surface become pseudo-leaves with a minimized vertex,
+    /// so the result is a valid but coarser octree.
+    StopRefining,
+    /// Abandon the build and return [`BuildCancelled`].
+    Abort,
+}
+
+/// The build was aborted by its progress callback.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BuildCancelled;
+
+impl std::fmt::Display for BuildCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("octree build was cancelled")
+    }
+}
+
+impl std::error::Error for BuildCancelled {}
+
+/// State threaded through the recursive build.
+struct BuildState<'a, P> {
+    options: &'a BuildOptions,
+    progress: P,
+    control: BuildControl,
+    /// Fraction of the root volume that has been fully built.
+    done_volume: f64,
+    budget_exhausted: bool,
+    /// [`BuildOptions::shell_iso_values`] relative to the iso value.
+    shell_levels: Vec<f32>,
+    /// The children of each branch on the recursion stack, 8 per branch, so
+    /// their IDs can be remapped when finished branches are collapsed.
+    pending_children: Vec<Option<CellId>>,
+}
+
+/// The QEFs accumulated for a cell's vertex.
+///
+/// Cells without vertices have default (zero) QEFs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CellQefs {
+    /// Minimized to find the vertex.
+    pub regularized: Qef,
+    /// Used to measure the vertex error.
+    pub exact: Qef,
 }
 
 impl CellOctree {
-    fn new(root_id: CellId, all_cells: Vec<Cell>) -> Self {
+    pub(crate) fn new(root_id: CellId, mut all_cells: Vec<Cell>, qefs: Vec<CellQefs>) -> Self {
+        link_parents(&mut all_cells);
+
+        // QEF minimizers are not constrained to their cells.
+        let vertex_escape = all_cells
+            .iter()
+            .filter(|c| c.is_leaf)
+            .map(vertex_escape)
+            .fold(0.0, f32::max);
         Self {
             root_id,
             all_cells,
+            qefs,
+            vertex_escape,
             ..Default::default()
         }
     }
 
+    /// The level set that this octree contours. Cell samples are relative to
+    /// this value.
+    pub fn iso_value(&self) -> f32 {
+        self.iso_value
+    }
+
+    /// `sdf` as this octree sampled it, before subtracting the iso value: capped
+    /// at the root cell if built with [`BuildOptions::cap_to_root`], otherwise
+    /// unchanged.
+    pub fn surface_sdf<'a>(&self, sdf: impl Fn(Vec3A) -> f32 + 'a) -> impl Fn(Vec3A) -> f32 + 'a {
+        let cap = self
+            .cap_inset
+            .zip(self.all_cells.get(self.root_id as usize))
+            .map(|(inset, root)| root_cap(&root.extent, inset));
+        let iso_value = self.iso_value;
+        move |p| match &cap {
+            Some(cap) => sdf(p).max(cap(p) + iso_value),
+            None => sdf(p),
+        }
+    }
+
+    pub fn root_id(&self) -> CellId {
+        self.root_id
+    }
+
     pub fn all_cells(&self) -> &[Cell] {
         &self.all_cells
     }
 
+    pub fn build_report(&self) -> Option<&BuildReport> {
+        self.build_report.as_ref()
+    }
+
+    /// The QEFs of every cell, parallel to [`Self::all_cells`], if the octree
+    /// was built with [`BuildOptions::store_qefs`].
+    pub fn qefs(&self) -> Option<&[CellQefs]> {
+        (!self.qefs.is_empty()).then_some(self.qefs.as_slice())
+    }
+
     pub(crate) fn clear_stacks(&mut self) {
         self.cell_stack.clear();
-        self.face_stack.clear();
-        self.edge_stack.clear();
+        self.work_stack.clear();
     }
 
     pub fn build(
@@ -42,69 +253,399 @@ impl CellOctree {
         precision: f32,
         sdf: impl Fn(Vec3A) -> f32,
     ) -> Option<Self> {
+        let options = BuildOptions {
+            max_depth,
+            error_tolerance,
+            precision,
+            ..Default::default()
+        };
+        Self::build_with_options(root_cell, &options, sdf)
+    }
+
+    pub fn build_with_options(
+        root_cell: Extent<Vec3A>,
+        options: &BuildOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> Option<Self> {
+        match Self::build_with_progress(root_cell, options, sdf, |_| BuildControl::Continue) {
+            Ok(octree) => octree,
+            Err(BuildCancelled) => unreachable!(),
+        }
+    }
+
+    /// Like [`Self::build_with_options`], but calls `progress` each time a
+    /// branch is finished.
+    ///
+    /// The callback decides whether to continue, so it can check a
+    /// cancellation flag or a deadline. A stopped build still returns an
+    /// octree (see [`BuildReport::stopped_refining`]), while an aborted build
+    /// returns an error.
+    pub fn build_with_progress(
+        root_cell: Extent<Vec3A>,
+        options: &BuildOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+        progress: impl FnMut(BuildProgress) -> BuildControl,
+    ) -> Result<Option<Self>, BuildCancelled> {
+        // Collapsing to fit the budget needs the QEFs of the leaves.
+        let drop_qefs = options.max_cells.is_some() && !options.store_qefs;
+        let budget_options;
+        let options = if drop_qefs {
+            budget_options = BuildOptions {
+                store_qefs: true,
+                ..options.clone()
+            };
+            &budget_options
+        } else {
+            options
+        };
+
+        let sdf_evaluations = std::cell::Cell::new(0);
+        let cap_inset = options.cap_to_root.then(|| {
+            let smallest_cell = root_cell.shape.min_element() / 2f32.powi(options.max_depth.into());
+            0.5 * smallest_cell
+        });
+        let cap = cap_inset.map(|inset| root_cap(&root_cell, inset));
+        let sdf = |p| {
+            sdf_evaluations.set(sdf_evaluations.get() + 1);
+            let d = sdf(p) - options.iso_value;
+            match &cap {
+                Some(cap) => d.max(cap(p)),
+                None => d,
+            }
+        };
+        let shell_levels: Vec<_> = options
+            .shell_iso_values
+            .iter()
+            .map(|v| v - options.iso_value)
+            .collect();
+
         let Some(mut root_cell) =
-            Cell::new(root_cell, &sdf, 0, max_depth == 0)
-            else { return None };
+            Cell::new(root_cell, sdf, 0, options.max_depth == 0, &shell_levels)
+            else { return Ok(None) };
+
+        let mut me = Self::default();
+        let mut state = BuildState {
+            options,
+            progress,
+            control: BuildControl::Continue,
+            done_volume: 0.0,
+            budget_exhausted: false,
+            shell_levels,
+            pending_children: Vec::new(),
+        };
+
+        let root_id = if root_cell.is_leaf {
+            let (regularized_qef, exact_qef) = root_cell.estimate_vertex(sdf, options.precision);
+            Some(me.push_cell(options, root_cell, regularized_qef, exact_qef))
+        } else {
+            me.build_recursive_from_branch(&mut state, &sdf, root_cell).0
+        };
+        if state.control == BuildControl::Abort {
+            return Err(BuildCancelled);
+        }
+        let Some(root_id) = root_id else { return Ok(None) };
 
-        if root_cell.is_leaf {
-            let _qef = root_cell.estimate_vertex(&sdf, precision);
-            return Some(Self::new(0, vec![root_cell]));
+        let mut octree = Self::new(root_id, me.all_cells, me.qefs);
+        if drop_qefs {
+            octree.qefs = Vec::new();
+        }
+        octree.iso_value = options.iso_value;
+        octree.cap_inset = cap_inset;
+        octree.build_report = Some(BuildReport {
+            sdf_evaluations: sdf_evaluations.get(),
+            stopped_refining: state.control == BuildControl::StopRefining
+                && !state.budget_exhausted,
+            budget_exhausted: state.budget_exhausted,
+        });
+        if options.compact {
+            octree.compact();
         }
+        Ok(Some(octree))
+    }
 
-        let mut me = Self::new(CellId::MAX, vec![]);
-        let (maybe_root_id, _) =
-            me.build_recursive_from_branch(max_depth, error_tolerance, precision, &sdf, root_cell);
+    /// Remove the descendants of pseudo-leaves, which are never visited by
+    /// [`Self::dual_contour`].
+    ///
+    /// The remaining cells keep their relative order. Returns the new ID of
+    /// each old cell, or `None` if it was removed.
+    pub fn compact(&mut self) -> Vec<Option<CellId>> {
+        if self.all_cells.is_empty() {
+            return Vec::new();
+        }
 
-        maybe_root_id.map(|root_id| {
-            me.root_id = root_id;
-            me
-        })
+        let mut keep = vec![false; self.all_cells.len()];
+        let mut stack = vec![self.root_id];
+        while let Some(cell_id) = stack.pop() {
+            keep[cell_id as usize] = true;
+            let cell = &self.all_cells[cell_id as usize];
+            if !cell.is_leaf {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+
+        let new_to_old: Vec<_> = (0..self.all_cells.len() as CellId)
+            .filter(|&id| keep[id as usize])
+            .collect();
+        self.remap_cells(&new_to_old)
+    }
+
+    /// Rebuild the octree with only the cells in `new_to_old`, in that order.
+    ///
+    /// Children that aren't kept are removed. Returns the mapping from old
+    /// to new cell IDs.
+    pub(crate) fn remap_cells(&mut self, new_to_old: &[CellId]) -> Vec<Option<CellId>> {
+        let mut old_to_new = vec![None; self.all_cells.len()];
+        for (new, &old) in new_to_old.iter().enumerate() {
+            old_to_new[old as usize] = Some(new as CellId);
+        }
+
+        let all_cells = new_to_old
+            .iter()
+            .map(|&old| {
+                let mut cell = self.all_cells[old as usize].clone();
+                cell.children = cell.children.map(|c| c.and_then(|c| old_to_new[c as usize]));
+                cell
+            })
+            .collect();
+        let qefs = if self.qefs.is_empty() {
+            Vec::new()
+        } else {
+            new_to_old
+                .iter()
+                .map(|&old| self.qefs[old as usize].clone())
+                .collect()
+        };
+        let corner_materials = if self.corner_materials.is_empty() {
+            Vec::new()
+        } else {
+            new_to_old
+                .iter()
+                .map(|&old| self.corner_materials[old as usize])
+                .collect()
+        };
+        let root_id = old_to_new[self.root_id as usize].expect("root must be kept");
+
+        let build_report = self.build_report.take();
+        let iso_value = self.iso_value;
+        let cap_inset = self.cap_inset;
+        *self = Self::new(root_id, all_cells, qefs);
+        self.corner_materials = corner_materials;
+        self.build_report = build_report;
+        self.iso_value = iso_value;
+        self.cap_inset = cap_inset;
+        old_to_new
+    }
+
+    pub(crate) fn push_cell(
+        &mut self,
+        options: &BuildOptions,
+        cell: Cell,
+        regularized: Qef,
+        exact: Qef,
+    ) -> CellId {
+        let id = self.all_cells.len() as CellId;
+        self.all_cells.push(cell);
+        if options.store_qefs {
+            self.qefs.push(CellQefs { regularized, exact });
+        }
+        id
+    }
+
+    /// Collapse the finished branches with the smallest QEF error into
+    /// pseudo-leaves until at most `target` cells are reachable, then remove
+    /// the unreachable cells.
+    ///
+    /// While building, the finished cells are the subtrees of
+    /// `pending_children`, whose IDs are remapped. Returns true if any branch
+    /// was collapsed.
+    fn collapse_finished_branches(
+        &mut self,
+        pending_children: &mut [Option<CellId>],
+        target: usize,
+    ) -> bool {
+        // Parents are linked again when the octree is finished.
+        let mut reachable = 0;
+        let mut candidates = BinaryHeap::new();
+        let mut stack: Vec<_> = pending_children.iter().flatten().copied().collect();
+        for &cell_id in &stack {
+            self.all_cells[cell_id as usize].parent = None;
+        }
+        while let Some(cell_id) = stack.pop() {
+            reachable += 1;
+            if self.all_cells[cell_id as usize].is_leaf {
+                continue;
+            }
+            for child in self.all_cells[cell_id as usize].children.into_iter().flatten() {
+                self.all_cells[child as usize].parent = Some(cell_id);
+                stack.push(child);
+            }
+            candidates.extend(self.collapse_candidate(cell_id));
+        }
+
+        let mut collapsed = false;
+        while reachable > target {
+            let Some(CollapseCandidate { cell_id, .. }) = candidates.pop() else { break };
+            let (regularized_qef, exact_qef) = self.summed_child_qefs(cell_id).unwrap();
+            let cell = &mut self.all_cells[cell_id as usize];
+            if regularized_qef == Qef::default() {
+                // No child has edge crossings.
+                cell.vertex_estimate = cell.extent.center().into();
+            } else {
+                cell.estimate_vertex_with_qef(&regularized_qef, &exact_qef);
+            }
+            cell.is_leaf = true;
+            reachable -= cell.children.iter().flatten().count();
+            let parent = cell.parent;
+            self.qefs[cell_id as usize] = CellQefs {
+                regularized: regularized_qef,
+                exact: exact_qef,
+            };
+            collapsed = true;
+
+            if let Some(parent) = parent {
+                candidates.extend(self.collapse_candidate(parent));
+            }
+        }
+
+        // Remove the unreachable cells in place, keeping the post-order.
+        let mut old_to_new = vec![None; self.all_cells.len()];
+        stack.extend(pending_children.iter().flatten());
+        while let Some(cell_id) = stack.pop() {
+            old_to_new[cell_id as usize] = Some(0);
+            let cell = &self.all_cells[cell_id as usize];
+            if !cell.is_leaf {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        let mut kept = 0;
+        for (old, new) in old_to_new.iter_mut().enumerate() {
+            if new.is_some() {
+                *new = Some(kept as CellId);
+                self.all_cells.swap(kept, old);
+                self.qefs.swap(kept, old);
+                kept += 1;
+            }
+        }
+        self.all_cells.truncate(kept);
+        self.qefs.truncate(kept);
+        for cell in &mut self.all_cells {
+            cell.children = cell.children.map(|c| c.and_then(|c| old_to_new[c as usize]));
+        }
+        for child in pending_children.iter_mut().flatten() {
+            *child = old_to_new[*child as usize].unwrap();
+        }
+
+        collapsed
+    }
+
+    /// The error of collapsing `cell_id` into a pseudo-leaf, if its children
+    /// are all leaves.
+    fn collapse_candidate(&self, cell_id: CellId) -> Option<CollapseCandidate> {
+        let (regularized_qef, exact_qef) = self.summed_child_qefs(cell_id)?;
+        let error = if regularized_qef == Qef::default() {
+            0.0
+        } else {
+            exact_qef.error(regularized_qef.minimizer())
+        };
+        Some(CollapseCandidate { error, cell_id })
+    }
+
+    /// The sums of the QEFs of the children of `cell_id`, if they are all
+    /// leaves.
+    fn summed_child_qefs(&self, cell_id: CellId) -> Option<(Qef, Qef)> {
+        let mut regularized_qef = Qef::default();
+        let mut exact_qef = Qef::default();
+        for &child in self.all_cells[cell_id as usize].children.iter().flatten() {
+            if !self.all_cells[child as usize].is_leaf {
+                return None;
+            }
+            let qefs = &self.qefs[child as usize];
+            regularized_qef = regularized_qef + qefs.regularized.clone();
+            exact_qef = exact_qef + qefs.exact.clone();
+        }
+        Some((regularized_qef, exact_qef))
     }
 
     // Recursive because it's easier and slightly more efficient for post-order
     // traversal.
     fn build_recursive_from_branch(
         &mut self,
-        max_depth: u8,
-        error_tolerance: f32,
-        precision: f32,
+        state: &mut BuildState<impl FnMut(BuildProgress) -> BuildControl>,
         sdf: &impl Fn(Vec3A) -> f32,
         mut branch: Cell,
     ) -> (Option<CellId>, VertexState) {
         assert!(!branch.is_leaf);
+        let options = state.options;
+        let child_volume = 8f64.powi(-(branch.depth as i32 + 1));
+
+        if let Some(max_cells) = options.max_cells {
+            // Once we stop refining, each cell on the recursion stack can
+            // still add itself and up to 7 leaf siblings, and this branch can
+            // add 8 leaf children.
+            let reserved = 8 * (branch.depth as usize + 2);
+            if state.control == BuildControl::Continue
+                && self.all_cells.len() + reserved > max_cells
+            {
+                // Make room with some slack, so we don't collapse again after
+                // every few cells.
+                let target = max_cells.saturating_sub(reserved) * 3 / 4;
+                if self.collapse_finished_branches(&mut state.pending_children, target) {
+                    state.budget_exhausted = true;
+                }
+                if self.all_cells.len() + reserved > max_cells {
+                    state.control = BuildControl::StopRefining;
+                    state.budget_exhausted = true;
+                }
+            }
+        }
 
         // Create all descendant cells.
         let mut sum_descendant_regularized_qef = Qef::default();
         let mut sum_descendant_exact_qef = Qef::default();
-        let mut child_cell_ids = [None; 8];
+        let first_child = state.pending_children.len();
+        state.pending_children.extend([None; 8]);
         let mut all_nonempty_children_can_merge = true;
         let mut any_nonempty_children = false;
         let mut has_vert = [false; 8];
-        let children = branch.get_children(sdf, branch.depth + 1 == max_depth);
-        for ((maybe_child, maybe_child_id), has_vert) in children
-            .into_iter()
-            .zip(&mut child_cell_ids)
-            .zip(&mut has_vert)
+        // Children are only created as leaves at the maximum depth. If we
+        // stop refining, the nonempty branches are collapsed below.
+        let at_max_depth = branch.depth + 1 == options.max_depth;
+        let children = branch.get_children(sdf, at_max_depth, &state.shell_levels);
+        for (octant, (maybe_child, has_vert)) in children.into_iter().zip(&mut has_vert).enumerate()
         {
-            let Some(mut child_cell) = maybe_child else { continue };
+            if state.control == BuildControl::Abort {
+                return (None, VertexState::EmptySpace);
+            }
+
+            let Some(mut child_cell) = maybe_child else {
+                state.done_volume += child_volume;
+                continue;
+            };
+
+            if !child_cell.is_leaf && state.control == BuildControl::StopRefining {
+                // Collapse the unvisited branch into a pseudo-leaf. Even if its
+                // corners don't straddle the surface, a finer neighbor's
+                // minimal edge on its boundary may need its vertex.
+                child_cell.is_leaf = true;
+            }
 
             if child_cell.is_leaf {
-                let (regularized_qef, exact_qef) = child_cell.estimate_vertex(sdf, precision);
-                sum_descendant_regularized_qef = sum_descendant_regularized_qef + regularized_qef;
-                sum_descendant_exact_qef = sum_descendant_exact_qef + exact_qef;
+                state.done_volume += child_volume;
+                if crosses_shell(&child_cell.samples, &state.shell_levels) {
+                    all_nonempty_children_can_merge = false;
+                }
+                let (regularized_qef, exact_qef) =
+                    child_cell.estimate_vertex(sdf, options.precision);
+                sum_descendant_regularized_qef =
+                    sum_descendant_regularized_qef + regularized_qef.clone();
+                sum_descendant_exact_qef = sum_descendant_exact_qef + exact_qef.clone();
 
                 any_nonempty_children = true;
-                let child_id = self.all_cells.len() as CellId;
-                self.all_cells.push(child_cell);
-                *maybe_child_id = Some(child_id);
+                let child_id = self.push_cell(options, child_cell, regularized_qef, exact_qef);
+                state.pending_children[first_child + octant] = Some(child_id);
             } else {
-                let (child_id, child_state) = self.build_recursive_from_branch(
-                    max_depth,
-                    error_tolerance,
-                    precision,
-                    sdf,
-                    child_cell,
-                );
+                let (child_id, child_state) =
+                    self.build_recursive_from_branch(state, sdf, child_cell);
                 match child_state {
                     VertexState::EmptySpace => {}
                     VertexState::CannotSimplify => {
@@ -122,9 +663,21 @@ impl CellOctree {
                         *has_vert = true;
                     }
                 }
-                *maybe_child_id = child_id;
+                state.pending_children[first_child + octant] = child_id;
             }
         }
+        let child_cell_ids: [_; 8] = state.pending_children[first_child..].try_into().unwrap();
+        state.pending_children.truncate(first_child);
+
+        if state.control == BuildControl::Abort {
+            return (None, VertexState::EmptySpace);
+        }
+        if state.control == BuildControl::Continue {
+            state.control = (state.progress)(BuildProgress {
+                fraction: state.done_volume.min(1.0) as f32,
+                cells: self.all_cells.len(),
+            });
+        }
 
         if !any_nonempty_children {
             // Empty branch.
@@ -136,14 +689,17 @@ impl CellOctree {
         // Post-order simplification can change branches into pseudo-leaves.
 
         let mut vertex_state = VertexState::CannotSimplify;
-        if all_nonempty_children_can_merge && cell_is_bipolar(&branch.samples) {
+        if all_nonempty_children_can_merge
+            && cell_is_bipolar(&branch.samples)
+            && !crosses_shell(&branch.samples, &state.shell_levels)
+        {
             // Branch vertex should be estimated. Only keep if it meets
             // error criterion.
             branch.estimate_vertex_with_qef(
                 &sum_descendant_regularized_qef,
                 &sum_descendant_exact_qef,
             );
-            if branch.qef_error <= error_tolerance {
+            if branch.qef_error <= options.error_tolerance {
                 // Simplify by choosing a vertex in this branch node.
                 branch.is_leaf = true; // pseudo-leaf
                 vertex_state = VertexState::HasVertex {
@@ -163,13 +719,71 @@ impl CellOctree {
             }
         }
 
-        let branch_id = self.all_cells.len() as CellId;
-        self.all_cells.push(branch);
+        let (regularized_qef, exact_qef) = match &vertex_state {
+            VertexState::HasVertex {
+                regularized_qef,
+                exact_qef,
+            } => (regularized_qef.clone(), exact_qef.clone()),
+            _ => Default::default(),
+        };
+        let branch_id = self.push_cell(options, branch, regularized_qef, exact_qef);
 
         (Some(branch_id), vertex_state)
     }
 }
 
+/// True if any of the relative `shell_levels` crosses the cell's corners.
+fn crosses_shell(samples: &[f32; 8], shell_levels: &[f32]) -> bool {
+    shell_levels
+        .iter()
+        .any(|&l| cell_is_bipolar(&samples.map(|s| s - l)))
+}
+
+/// Ordered so that the candidate with the smallest error is popped first from a
+/// max-heap.
+struct CollapseCandidate {
+    error: f32,
+    cell_id: CellId,
+}
+
+impl PartialEq for CollapseCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CollapseCandidate {}
+
+impl PartialOrd for CollapseCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CollapseCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.error.total_cmp(&self.error)
+    }
+}
+
+fn link_parents(all_cells: &mut [Cell]) {
+    for parent_id in 0..all_cells.len() {
+        for child in all_cells[parent_id].children.into_iter().flatten() {
+            all_cells[child as usize].parent = Some(parent_id as CellId);
+        }
+    }
+}
+
+/// How far the vertex of `cell` lies outside of it.
+pub(crate) fn vertex_escape(cell: &Cell) -> f32 {
+    distance_to_extent(&cell.extent, cell.vertex_estimate.into())
+}
+
+pub(crate) fn distance_to_extent(extent: &Extent<Vec3A>, p: Vec3A) -> f32 {
+    let nearest = p.clamp(extent.minimum, extent.minimum + extent.shape);
+    p.distance(nearest)
+}
+
 #[derive(Debug)]
 enum VertexState {
     EmptySpace,
@@ -187,15 +801,19 @@ pub struct Cell {
     // PERF: replace with a smaller octant identifier; extent should be implicit
     pub extent: Extent<Vec3A>,
 
+    /// SDF values at the corners, minus [`CellOctree::iso_value`].
     pub samples: [f32; 8],
     pub children: [Option<CellId>; 8], // PERF: nonzero/nonmax?
+    /// `None` for the root.
+    pub parent: Option<CellId>,
 
-    // TODO: remove these when meshes are managed separately
     /// We don't use `Vec3A` because it's 16-byte-aligned.
     pub vertex_estimate: Vec3,
 
-    pub mesh_vertex_id: MeshVertexId,
     pub qef_error: f32,
+    /// Classification of `vertex_estimate` from the spectrum of the cell's
+    /// Hermite normals.
+    pub feature: SharpFeature,
 
     pub depth: u8,
     pub is_leaf: bool,
@@ -207,6 +825,7 @@ impl Cell {
         sdf: impl Fn(Vec3A) -> f32,
         depth: u8,
         is_leaf: bool,
+        shell_levels: &[f32],
     ) -> Option<Self> {
         let cell_positions = extent.corners3();
         // PERF: we could pretty easily make 3^3 samples/taps instead of 2^3 * 2^3 when splitting an octant
@@ -214,8 +833,12 @@ impl Cell {
         let samples = cell_positions.map(&sdf);
 
         // Leaf cells must be bipolar. Branches are checked optimistically.
-        if (is_leaf && !cell_is_bipolar(&samples))
-            || branch_empty_check(extent.shape.length(), &samples)
+        let diagonal = extent.shape.length();
+        if (is_leaf && !cell_is_bipolar(&samples) && !crosses_shell(&samples, shell_levels))
+            || (branch_empty_check(diagonal, &samples)
+                && shell_levels
+                    .iter()
+                    .all(|&l| branch_empty_check(diagonal, &samples.map(|s| s - l))))
         {
             return None;
         }
@@ -224,23 +847,35 @@ impl Cell {
             extent,
             samples,
             children: [None; 8],
+            parent: None,
             vertex_estimate: Vec3::ZERO,
-            mesh_vertex_id: NULL_MESH_VERTEX_ID,
             qef_error: 0.0,
+            feature: SharpFeature::Smooth,
             is_leaf,
             depth,
         })
     }
 
     #[inline]
-    fn get_children(&self, sdf: impl Fn(Vec3A) -> f32, is_leaf: bool) -> [Option<Self>; 8] {
+    fn get_children(
+        &self,
+        sdf: impl Fn(Vec3A) -> f32,
+        is_leaf: bool,
+        shell_levels: &[f32],
+    ) -> [Option<Self>; 8] {
         assert!(!self.is_leaf);
         let child_extents = self.extent.split3(self.extent.center());
-        child_extents.map(|extent| Self::new(extent, &sdf, self.depth + 1, is_leaf))
+        child_extents.map(|extent| Self::new(extent, &sdf, self.depth + 1, is_leaf, shell_levels))
     }
 
     #[inline]
     fn estimate_vertex(&mut self, sdf: impl Fn(Vec3A) -> f32, precision: f32) -> (Qef, Qef) {
+        if !cell_is_bipolar(&self.samples) {
+            // Only a shell crosses this leaf, or it was collapsed when the
+            // build stopped refining, so there are no edge crossings.
+            self.vertex_estimate = self.extent.center().into();
+            return Default::default();
+        }
         let (regularized_qef, exact_qef) =
             estimate_interior_vertex_qef(&self.extent, &self.samples, &sdf, precision);
         self.estimate_vertex_with_qef(&regularized_qef, &exact_qef);
@@ -248,23 +883,357 @@ impl Cell {
     }
 
     #[inline]
-    fn estimate_vertex_with_qef(&mut self, regularized_qef: &Qef, exact_qef: &Qef) {
+    pub(crate) fn estimate_vertex_with_qef(&mut self, regularized_qef: &Qef, exact_qef: &Qef) {
         let p = regularized_qef.minimizer();
         self.qef_error = exact_qef.error(p);
         self.vertex_estimate = p.into();
+        self.feature = SharpFeature::from_qef(exact_qef);
     }
 }
 
+/// A pair of cells that share a face, given from the `-axis` side to the
+/// `+axis` side.
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct Face {
+pub struct Face {
     pub axis: usize,
     pub cells: [CellId; 2],
 }
 
+/// An item of the contouring traversal.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ContourWork {
+    /// All faces and then all edges in the interior of a cell.
+    Cell(CellId),
+    /// The faces between the descendants of a cell.
+    CellFaces(CellId),
+    /// The edges between the descendants of a cell that aren't on those
+    /// faces.
+    CellEdges(CellId),
+    Face(Face),
+    Edge(Edge),
+}
+
+/// Four cells that share an edge parallel to `axis`, given in Z order.
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct Edge {
+pub struct Edge {
     pub axis: usize,
     pub cells: [CellId; 4],
     /// True if the corresponding cell appears twice on this edge.
     pub is_duplicate: [bool; 4],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives, MeshOptions};
+    use std::collections::HashMap;
+
+    fn sdf(p: Vec3A) -> f32 {
+        sdf_primitives::cube(Vec3A::splat(0.5), p).min(sdf_primitives::sphere(0.4, p - 0.4))
+    }
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        CellOctree::build(root, 5, 0.01, 0.1, sdf).unwrap()
+    }
+
+    /// Assert that the triangles have no boundary: each edge is used as often
+    /// in one direction as in the other.
+    ///
+    /// Dual contouring can make non-manifold edges, used by 4 triangles,
+    /// where a coarse cell has more than one sheet of surface.
+    fn assert_watertight(tri_indices: &[u32]) {
+        let edges = directed_edges(tri_indices);
+        assert!(!edges.is_empty());
+        for (&(a, b), count) in &edges {
+            assert_eq!(edges.get(&(b, a)), Some(count), "edge {a}-{b} is open");
+        }
+    }
+
+    /// The number of times each directed edge is used by the triangles.
+    fn directed_edges(tri_indices: &[u32]) -> HashMap<(u32, u32), usize> {
+        let mut edges = HashMap::new();
+        for tri in tri_indices.chunks_exact(3) {
+            for i in 0..3 {
+                *edges.entry((tri[i], tri[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    fn sphere_options() -> BuildOptions {
+        BuildOptions {
+            max_depth: 6,
+            error_tolerance: 0.0,
+            ..Default::default()
+        }
+    }
+
+    /// Without splitting vertices at sharp normals, which would open the mesh.
+    fn welded() -> MeshOptions {
+        MeshOptions {
+            sharp_normal_threshold: None,
+            ..Default::default()
+        }
+    }
+
+    fn root() -> Extent<Vec3A> {
+        Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0))
+    }
+
+    #[test]
+    fn progress_callback_can_cancel() {
+        let sdf = |p| sdf_primitives::sphere(0.6, p);
+        let mut calls = 0;
+        let result = CellOctree::build_with_progress(root(), &sphere_options(), sdf, |_| {
+            calls += 1;
+            if calls == 10 {
+                BuildControl::Abort
+            } else {
+                BuildControl::Continue
+            }
+        });
+        assert_eq!(result.unwrap_err(), BuildCancelled);
+        assert_eq!(calls, 10);
+
+        let mut fractions = Vec::new();
+        let octree = CellOctree::build_with_progress(root(), &sphere_options(), sdf, |p| {
+            fractions.push(p.fraction);
+            BuildControl::Continue
+        })
+        .unwrap()
+        .unwrap();
+        assert!(fractions.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!((fractions.last().unwrap() - 1.0).abs() < 1e-5);
+        let report = octree.build_report().unwrap();
+        assert!(!report.stopped_refining && !report.budget_exhausted);
+    }
+
+    #[test]
+    fn stop_refining_leaves_watertight_mesh() {
+        let sdf = |p| sdf_primitives::sphere(0.6, p);
+        let mut full = CellOctree::build_with_options(root(), &sphere_options(), sdf).unwrap();
+        let full_mesh = full.contour_to_mesh(&welded(), sdf);
+        assert_watertight(&full_mesh.tri_indices);
+
+        for stop_after in [1, 5, 20, 100] {
+            let mut calls = 0;
+            let mut octree =
+                CellOctree::build_with_progress(root(), &sphere_options(), sdf, |_| {
+                    calls += 1;
+                    if calls == stop_after {
+                        BuildControl::StopRefining
+                    } else {
+                        BuildControl::Continue
+                    }
+                })
+                .unwrap()
+                .unwrap();
+            assert_eq!(calls, stop_after);
+            assert!(octree.build_report().unwrap().stopped_refining);
+            assert!(octree.all_cells.len() < full.all_cells.len());
+
+            let mesh = octree.contour_to_mesh(&welded(), sdf);
+            assert!(mesh.positions.len() < full_mesh.positions.len());
+            assert_watertight(&mesh.tri_indices);
+        }
+    }
+
+    #[test]
+    fn stop_refining_keeps_unvisited_branches_without_crossings() {
+        // The sphere straddles the face between the first two octants of the
+        // root, but none of their corners.
+        let sdf = |p| sdf_primitives::sphere(0.3, p - Vec3A::new(0.0, -0.5, -0.5));
+        let mut octree = CellOctree::build_with_progress(root(), &sphere_options(), sdf, |p| {
+            // Stop once the first octant is done.
+            if p.fraction >= 0.125 {
+                BuildControl::StopRefining
+            } else {
+                BuildControl::Continue
+            }
+        })
+        .unwrap()
+        .unwrap();
+        let root_cell = &octree.all_cells[octree.root_id as usize];
+        let second = &octree.all_cells[root_cell.children[1].unwrap() as usize];
+        assert!(second.is_leaf && !cell_is_bipolar(&second.samples));
+
+        let mesh = octree.contour_to_mesh(&welded(), sdf);
+        assert_watertight(&mesh.tri_indices);
+    }
+
+    #[test]
+    fn max_cells_bounds_octree_size() {
+        let sdf = |p| sdf_primitives::sphere(0.6, p);
+        let full = CellOctree::build_with_options(root(), &sphere_options(), sdf).unwrap();
+        assert!(!full.build_report().unwrap().budget_exhausted);
+
+        for max_cells in [0, 9, 50, 200, full.all_cells.len() / 2] {
+            let options = BuildOptions {
+                max_cells: Some(max_cells),
+                ..sphere_options()
+            };
+            let mut octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+            assert!(octree.all_cells.len() <= max_cells.max(9));
+            let report = octree.build_report().unwrap();
+            assert!(report.budget_exhausted && !report.stopped_refining);
+
+            let mesh = octree.contour_to_mesh(&welded(), sdf);
+            assert_watertight(&mesh.tri_indices);
+        }
+
+        // A budget that isn't reached changes nothing.
+        let options = BuildOptions {
+            max_cells: Some(full.all_cells.len() + 100),
+            ..sphere_options()
+        };
+        let octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+        assert_eq!(octree.all_cells.len(), full.all_cells.len());
+        assert!(!octree.build_report().unwrap().budget_exhausted);
+    }
+
+    #[test]
+    fn max_cells_refines_worst_error_regions() {
+        // The sphere is built first and is smooth, while the box is built
+        // last and has sharp edges.
+        let sdf = |p: Vec3A| {
+            sdf_primitives::sphere(0.4, p + 0.5)
+                .min(sdf_primitives::cube(Vec3A::splat(0.3), p - 0.5))
+        };
+        let corner = Vec3A::splat(0.79);
+        let full = CellOctree::build_with_options(root(), &sphere_options(), sdf).unwrap();
+        let full_corner = full.find_leaf(corner).unwrap();
+        let max_depth = full.all_cells[full_corner as usize].depth;
+        assert_eq!(max_depth, sphere_options().max_depth);
+
+        let options = BuildOptions {
+            max_cells: Some(full.all_cells.len() / 4),
+            ..sphere_options()
+        };
+        let mut octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+        assert!(octree.build_report().unwrap().budget_exhausted);
+        assert!(octree.all_cells.len() <= full.all_cells.len() / 4);
+        assert!(octree.qefs.is_empty());
+
+        // The budget is taken from the sphere, not the box.
+        let leaf_depth = |p| octree.all_cells[octree.find_leaf(p).unwrap() as usize].depth;
+        assert_eq!(leaf_depth(corner), max_depth);
+        assert!(leaf_depth(Vec3A::splat(-0.5 + 0.4 / 3f32.sqrt())) < max_depth);
+
+        let mesh = octree.contour_to_mesh(&welded(), sdf);
+        assert_watertight(&mesh.tri_indices);
+    }
+
+    #[test]
+    fn cap_to_root_closes_mesh() {
+        // The box pokes out of the +X face of the root.
+        let sdf = |p: Vec3A| sdf_primitives::cube(Vec3A::splat(0.7), p - Vec3A::new(0.5, 0.0, 0.0));
+        // Simplification can make non-manifold edges, so it's disabled.
+        let options = BuildOptions {
+            max_depth: 5,
+            error_tolerance: -1.0,
+            cap_to_root: true,
+            ..Default::default()
+        };
+
+        let open_options = BuildOptions {
+            cap_to_root: false,
+            ..options.clone()
+        };
+        let mut open = CellOctree::build_with_options(root(), &open_options, sdf).unwrap();
+        let mesh = open.contour_to_mesh(&welded(), sdf);
+        let edges = directed_edges(&mesh.tri_indices);
+        assert!(edges.keys().any(|&(a, b)| !edges.contains_key(&(b, a))));
+
+        let mut octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+        let mesh = octree.contour_to_mesh(&welded(), sdf);
+
+        // Every edge is used once in each direction, so the mesh is closed,
+        // manifold and consistently oriented.
+        let edges = directed_edges(&mesh.tri_indices);
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        // Facing outward, with the volume of the box inside the root.
+        let volume: f32 = mesh
+            .tri_indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let [p0, p1, p2] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+                p0.dot(p1.cross(p2)) / 6.0
+            })
+            .sum();
+        let inset = octree.cap_inset.unwrap();
+        assert_eq!(inset, 2.0 / 64.0);
+        let expected = (1.2 - inset) * 1.4 * 1.4;
+        assert!((volume - expected).abs() < 0.05, "{volume} != {expected}");
+
+        // The cap has flat normals.
+        let cap_sdf = octree.surface_sdf(sdf);
+        assert!(cap_sdf(Vec3A::new(1.0 - 0.5 * inset, 0.0, 0.0)) > 0.0);
+        let cap_normals: Vec<_> = mesh
+            .positions
+            .iter()
+            .zip(&mesh.normals)
+            .filter(|(p, _)| p.x > 1.0 - 2.0 * inset && p.y.abs() < 0.6 && p.z.abs() < 0.6)
+            .map(|(_, n)| *n)
+            .collect();
+        assert!(!cap_normals.is_empty());
+        assert!(cap_normals.iter().all(|n| n.dot(Vec3A::X) > 0.99));
+    }
+
+    #[test]
+    fn compact_keeps_contours_identical() {
+        let mut original = octree();
+        let mut compacted = octree();
+        let old_to_new = compacted.compact();
+        assert_eq!(old_to_new.len(), original.all_cells.len());
+        assert!(compacted.all_cells.len() < original.all_cells.len());
+
+        // Kept cells keep their relative order and contents.
+        let kept: Vec<_> = old_to_new.iter().flatten().copied().collect();
+        let expected: Vec<_> = (0..compacted.all_cells.len() as CellId).collect();
+        assert_eq!(kept, expected);
+        for (old, new) in old_to_new.iter().enumerate() {
+            if let Some(new) = new {
+                let a = &original.all_cells[old];
+                let b = &compacted.all_cells[*new as usize];
+                assert_eq!(a.extent, b.extent);
+                assert_eq!(a.vertex_estimate, b.vertex_estimate);
+                assert_eq!(a.is_leaf, b.is_leaf);
+                assert_eq!(a.parent.map(|p| old_to_new[p as usize].unwrap()), b.parent);
+            }
+        }
+        assert_eq!(
+            Some(compacted.root_id),
+            old_to_new[original.root_id as usize]
+        );
+        // Nothing below a leaf survives.
+        for cell in &compacted.all_cells {
+            if cell.is_leaf {
+                assert_eq!(cell.children, [None; 8]);
+            }
+        }
+
+        let options = MeshOptions::default();
+        let expected = original.contour_to_mesh(&options, sdf);
+        let actual = compacted.contour_to_mesh(&options, sdf);
+        assert_eq!(actual.positions, expected.positions);
+        assert_eq!(actual.normals, expected.normals);
+        assert_eq!(actual.tri_indices, expected.tri_indices);
+        let remapped: Vec<_> = expected
+            .cell_ids
+            .iter()
+            .map(|&id| old_to_new[id as usize].unwrap())
+            .collect();
+        assert_eq!(actual.cell_ids, remapped);
+
+        // Compacting again is a no-op.
+        let old_to_new = compacted.compact();
+        for (old, new) in old_to_new.into_iter().enumerate() {
+            assert_eq!(new, Some(old as CellId));
+        }
+    }
+}
@@ -1,12 +1,113 @@
-use crate::{tables::*, Cell, CellId, CellOctree, Edge, Face};
+use crate::{
+    cell_octree::vertex_escape, tables::*, Cell, CellId, CellOctree, ContourWork, Edge, Face,
+};
+use glam::Vec3A;
+use ilattice::extent::Extent;
+
+/// Receives the output of [`CellOctree::dual_contour_with`].
+///
+/// Only the leaf, quad and triangle methods are required.
+pub trait ContourVisitor {
+    /// Called once for each leaf, before any polygons.
+    fn visit_leaf(&mut self, cell_id: CellId, cell: &mut Cell);
+
+    /// The cells around a bipolar edge. These are in Z order around the
+    /// edge, so `[c[0], c[2], c[3], c[1]]` is the winding order.
+    fn visit_quad(&mut self, cells: [CellId; 4]);
+
+    /// Like `visit_quad`, where one cell appeared twice around the edge. These
+    /// are in winding order.
+    fn visit_triangle(&mut self, cells: [CellId; 3]);
+
+    /// Called for each pair of cells whose shared face is searched for edges.
+    fn visit_face(&mut self, _face: &Face) {}
+
+    /// Called for each group of cells whose shared edge is searched for
+    /// bipolar edges.
+    fn visit_edge(&mut self, _edge: &Edge) {}
+
+    /// Called for each bipolar minimal edge, just before the polygon that
+    /// crosses it.
+    fn visit_minimal_edge(&mut self, _edge: &MinimalEdge) {}
+}
+
+impl<L, Q, T> ContourVisitor for (L, Q, T)
+where
+    L: FnMut(CellId, &mut Cell),
+    Q: FnMut([CellId; 4]),
+    T: FnMut([CellId; 3]),
+{
+    fn visit_leaf(&mut self, cell_id: CellId, cell: &mut Cell) {
+        (self.0)(cell_id, cell)
+    }
+
+    fn visit_quad(&mut self, cells: [CellId; 4]) {
+        (self.1)(cells)
+    }
+
+    fn visit_triangle(&mut self, cells: [CellId; 3]) {
+        (self.2)(cells)
+    }
+}
+
+/// Hermite data of the smallest edge between 4 leaves, which a quad or
+/// triangle crosses.
+#[derive(Clone, Copy, Debug)]
+pub struct MinimalEdge {
+    /// The leaves around the edge.
+    pub edge: Edge,
+    /// The smallest leaf, which has this edge.
+    pub cell_id: CellId,
+    /// The corners of `cell_id` at `endpoints`.
+    pub corners: [u8; 2],
+    /// Endpoints in increasing order along `edge.axis`.
+    pub endpoints: [Vec3A; 2],
+    /// SDF samples at `endpoints`, which have opposite signs.
+    pub samples: [f32; 2],
+    /// Unit gradient at [`Self::crossing`] of the trilinear interpolation of
+    /// the corner samples of `cell_id`.
+    pub normal: Vec3A,
+}
+
+impl MinimalEdge {
+    /// The axis that the edge is parallel to. The polygon visited after this
+    /// edge is wound to face `sign() * axis`.
+    pub fn axis(&self) -> usize {
+        self.edge.axis
+    }
+
+    /// `1` if the SDF increases along `edge.axis`, so the surface faces
+    /// `+axis`, otherwise `-1`.
+    pub fn sign(&self) -> i8 {
+        if self.samples[0] < 0.0 {
+            1
+        } else {
+            -1
+        }
+    }
+
+    /// Where the linear interpolation of `samples` crosses zero.
+    pub fn crossing(&self) -> Vec3A {
+        let [d0, d1] = self.samples;
+        let t = d0 / (d0 - d1);
+        self.endpoints[0].lerp(self.endpoints[1], t)
+    }
+}
 
 impl CellOctree {
+    /// Visit all leaves and then all polygons of the isosurface.
+    ///
+    /// See [`ContourVisitor`].
     pub fn dual_contour(
         &mut self,
-        mut visit_leaf_cell: impl FnMut(CellId, &mut Cell),
-        mut visit_quad: impl FnMut([CellId; 4]),
-        mut visit_triangle: impl FnMut([CellId; 3]),
+        visit_leaf_cell: impl FnMut(CellId, &mut Cell),
+        visit_quad: impl FnMut([CellId; 4]),
+        visit_triangle: impl FnMut([CellId; 3]),
     ) {
+        self.dual_contour_with(&mut (visit_leaf_cell, visit_quad, visit_triangle))
+    }
+
+    pub fn dual_contour_with(&mut self, visitor: &mut impl ContourVisitor) {
         self.clear_stacks();
 
         // The general strategy for isosurface extraction via dual contouring is
@@ -15,88 +116,242 @@ impl CellOctree {
         // These isosurface facets connect vertices at the center of each cell
         // sharing the bipolar edge.
         //
-        // The non-recursive algorithm requires 3 stacks that are consumed in
-        // this order. One downside is that the face and edge stacks may get
-        // very large while traversing all cells first.
-        //
-        // PERF: we might get away with starting to consume faces and edges
-        // before the cells are complete
+        // All leaves are visited first, so callers can create a vertex for
+        // each leaf before any polygon refers to it.
+        self.cell_stack.push(self.root_id);
+        while let Some(cell_id) = self.cell_stack.pop() {
+            let cell = &mut self.all_cells[cell_id as usize];
+            if cell.is_leaf {
+                visitor.visit_leaf(cell_id, cell);
+                // The visitor may have moved the vertex.
+                self.vertex_escape = self.vertex_escape.max(vertex_escape(cell));
+            } else {
+                self.cell_stack.extend(cell.children.iter().flatten());
+            }
+        }
+
+        // Then we search for interior faces and edges. Consuming them in LIFO
+        // order along with the cells that produce them keeps the stack size
+        // proportional to the depth of the tree, while the polygons come in
+        // the same order as when all cells, then all faces, then all edges
+        // were consumed from separate stacks.
+        self.work_stack.push(ContourWork::Cell(self.root_id));
+        while let Some(work) = self.work_stack.pop() {
+            contour_work(&self.all_cells, &mut self.work_stack, work, visitor);
+        }
+    }
+}
+
+impl CellOctree {
+    /// Like [`Self::dual_contour_with`], but only visits the polygons whose
+    /// minimal edge has its midpoint in `region`, and skips subtrees that
+    /// can't contain such edges.
+    ///
+    /// The region includes its minimum and excludes its least upper bound, so
+    /// regions that tile space produce each polygon exactly once. Leaves are
+    /// visited if they touch `region`, which includes all of the leaves
+    /// referenced by the polygons.
+    pub fn dual_contour_in(&mut self, region: &Extent<Vec3A>, visitor: &mut impl ContourVisitor) {
+        self.clear_stacks();
+        if self.all_cells.is_empty() {
+            return;
+        }
 
-        // Start from the root again to seed contouring.
         self.cell_stack.push(self.root_id);
-        while let Some(cell) = self.cell_stack.pop() {
-            contour_cell_interior(self, cell, &mut visit_leaf_cell);
+        while let Some(cell_id) = self.cell_stack.pop() {
+            let cell = &mut self.all_cells[cell_id as usize];
+            if !touches(cell.extent.minimum, cell.extent.least_upper_bound(), region) {
+                continue;
+            }
+            if cell.is_leaf {
+                visitor.visit_leaf(cell_id, cell);
+                // The visitor may have moved the vertex.
+                self.vertex_escape = self.vertex_escape.max(vertex_escape(cell));
+            } else {
+                self.cell_stack.extend(cell.children.iter().flatten());
+            }
+        }
+
+        let mut visitor = RegionVisitor {
+            visitor,
+            region,
+            owns_polygon: false,
+        };
+        self.work_stack.push(ContourWork::Cell(self.root_id));
+        while let Some(work) = self.work_stack.pop() {
+            // Faces and edges are where all of their cells meet.
+            let cell_ids = match &work {
+                ContourWork::Cell(cell)
+                | ContourWork::CellFaces(cell)
+                | ContourWork::CellEdges(cell) => std::slice::from_ref(cell),
+                ContourWork::Face(face) => &face.cells[..],
+                ContourWork::Edge(edge) => &edge.cells[..],
+            };
+            let mut min = Vec3A::splat(f32::NEG_INFINITY);
+            let mut max = Vec3A::splat(f32::INFINITY);
+            for &id in cell_ids {
+                let extent = &self.all_cells[id as usize].extent;
+                min = min.max(extent.minimum);
+                max = max.min(extent.minimum + extent.shape);
+            }
+            if !touches(min, max, region) {
+                continue;
+            }
+
+            contour_work(&self.all_cells, &mut self.work_stack, work, &mut visitor);
         }
+    }
+}
+
+/// True if the closed box from `min` to `max` intersects the closure of
+/// `region`.
+fn touches(min: Vec3A, max: Vec3A, region: &Extent<Vec3A>) -> bool {
+    min.cmple(region.least_upper_bound()).all() && region.minimum.cmple(max).all()
+}
 
-        // Search for interior faces.
-        while let Some(face) = self.face_stack.pop() {
-            contour_face_interior(self, face);
+/// Filters polygons by the position of their minimal edges.
+struct RegionVisitor<'a, V> {
+    visitor: &'a mut V,
+    region: &'a Extent<Vec3A>,
+    /// Whether the last minimal edge was in the region.
+    owns_polygon: bool,
+}
+
+impl<'a, V: ContourVisitor> ContourVisitor for RegionVisitor<'a, V> {
+    fn visit_leaf(&mut self, cell_id: CellId, cell: &mut Cell) {
+        self.visitor.visit_leaf(cell_id, cell)
+    }
+
+    fn visit_quad(&mut self, cells: [CellId; 4]) {
+        if self.owns_polygon {
+            self.visitor.visit_quad(cells)
+        }
+    }
+
+    fn visit_triangle(&mut self, cells: [CellId; 3]) {
+        if self.owns_polygon {
+            self.visitor.visit_triangle(cells)
         }
+    }
+
+    fn visit_face(&mut self, face: &Face) {
+        self.visitor.visit_face(face)
+    }
+
+    fn visit_edge(&mut self, edge: &Edge) {
+        self.visitor.visit_edge(edge)
+    }
 
-        // Search for interior edges.
-        while let Some(edge) = self.edge_stack.pop() {
-            contour_edge_interior(self, edge, &mut visit_quad, &mut visit_triangle);
+    fn visit_minimal_edge(&mut self, edge: &MinimalEdge) {
+        let midpoint = 0.5 * (edge.endpoints[0] + edge.endpoints[1]);
+        self.owns_polygon = midpoint.cmpge(self.region.minimum).all()
+            && midpoint.cmplt(self.region.least_upper_bound()).all();
+        if self.owns_polygon {
+            self.visitor.visit_minimal_edge(edge)
         }
     }
 }
 
-// 8 cells, 12 faces, 6 edges
+/// Process one item of the traversal, pushing any items it produces onto
+/// `stack`.
 #[inline]
-fn contour_cell_interior(
-    octree: &mut CellOctree,
-    cell_id: CellId,
-    visit_leaf_cell: &mut impl FnMut(CellId, &mut Cell),
+pub(crate) fn contour_work(
+    cells: &[Cell],
+    stack: &mut Vec<ContourWork>,
+    work: ContourWork,
+    visitor: &mut impl ContourVisitor,
 ) {
-    let cell = &mut octree.all_cells[cell_id as usize];
+    match work {
+        ContourWork::Cell(cell) => {
+            // Like the 3 stacks of cells, faces and edges that this traversal
+            // replaced, all faces are searched before the edges of any cell.
+            stack.push(ContourWork::CellEdges(cell));
+            stack.push(ContourWork::CellFaces(cell));
+        }
+        ContourWork::CellFaces(cell) => contour_cell_faces(cells, stack, cell),
+        ContourWork::CellEdges(cell) => contour_cell_edges(cells, stack, cell),
+        ContourWork::Face(face) => {
+            visitor.visit_face(&face);
+            contour_face_interior(cells, stack, face)
+        }
+        ContourWork::Edge(edge) => {
+            visitor.visit_edge(&edge);
+            contour_edge_interior(cells, stack, edge, visitor)
+        }
+    }
+}
+
+// 8 cells, 12 faces
+#[inline]
+fn contour_cell_faces(cells: &[Cell], stack: &mut Vec<ContourWork>, cell_id: CellId) {
+    let cell = &cells[cell_id as usize];
 
     if cell.is_leaf {
-        // If we've reached the desired level of detail, run a user-defined
-        // interior contouring routine.
-        visit_leaf_cell(cell_id, cell);
+        // Leaves were already visited.
         return;
     }
 
-    // Recursively identify all bipolar edges on the interior of the parent cell.
-
+    // Recursively identify all bipolar edges on the interior of the parent
+    // cell. The children are searched from the last octant to the first,
+    // after the faces of this cell.
     for &child in cell.children.iter().flatten() {
-        octree.cell_stack.push(child);
+        stack.push(ContourWork::CellFaces(child));
     }
 
     // What remains of the interior of the parent cell can be found entirely
-    // in the face interiors and edge interiors of the descendant cells.
-    for axis in 0..3 {
-        for face in 0..4 {
+    // in the face interiors and edge interiors of the descendant cells. Push
+    // the faces in reverse so they're searched in order.
+    for axis in (0..3).rev() {
+        for face in (0..4).rev() {
             let face_cell_ids =
                 FACE_ADJACENT_OCTANTS[axis][face].map(|o| cell.children[o as usize]);
             if let [Some(f0), Some(f1)] = face_cell_ids {
-                octree.face_stack.push(Face {
+                stack.push(ContourWork::Face(Face {
                     axis,
                     cells: [f0, f1],
-                });
+                }));
             }
         }
+    }
+}
+
+// 8 cells, 6 edges
+#[inline]
+fn contour_cell_edges(cells: &[Cell], stack: &mut Vec<ContourWork>, cell_id: CellId) {
+    let cell = &cells[cell_id as usize];
+
+    if cell.is_leaf {
+        return;
+    }
 
+    // The edges of this cell are searched from last to first, after the
+    // children from the first octant to the last.
+    for axis in 0..3 {
         for edge in 0..2 {
             // Because we just began partitioning this cell, we know there
             // are 4 children intersecting each edge.
             let edge_cells = EDGE_ADJACENT_OCTANTS[axis][edge].map(|o| cell.children[o as usize]);
             if let [Some(e0), Some(e1), Some(e2), Some(e3)] = edge_cells {
-                octree.edge_stack.push(Edge {
+                stack.push(ContourWork::Edge(Edge {
                     axis,
                     cells: [e0, e1, e2, e3],
                     is_duplicate: [false; 4],
-                });
+                }));
             }
         }
     }
+
+    for &child in cell.children.iter().rev().flatten() {
+        stack.push(ContourWork::CellEdges(child));
+    }
 }
 
 // 4 faces and 4 edges
 #[inline]
-fn contour_face_interior(octree: &mut CellOctree, face: Face) {
+fn contour_face_interior(cells: &[Cell], stack: &mut Vec<ContourWork>, face: Face) {
     // PRECONDITION: `face` cells are given in increasing order (- side of face to + side).
 
-    let face_cells = face.cells.map(|i| &octree.all_cells[i as usize]);
+    let face_cells = face.cells.map(|i| &cells[i as usize]);
 
     if face_cells[0].is_leaf && face_cells[1].is_leaf {
         // No edges on the face interior.
@@ -119,8 +374,8 @@ fn contour_face_interior(octree: &mut CellOctree, face: Face) {
     //   f0    f1
     // ```
 
-    let mut get_child_cell_id = |parent: CellId, octant: u8| -> (Option<CellId>, bool) {
-        let cell = &mut octree.all_cells[parent as usize];
+    let get_child_cell_id = |parent: CellId, octant: u8| -> (Option<CellId>, bool) {
+        let cell = &cells[parent as usize];
         if cell.is_leaf {
             // Continue participation. Note that we may cause a duplicate on an
             // edge.
@@ -130,21 +385,8 @@ fn contour_face_interior(octree: &mut CellOctree, face: Face) {
         }
     };
 
-    for face_i in 0..4 {
-        let o = FACE_ADJACENT_OCTANTS[face.axis][face_i];
-
-        // Mirror permutation is independent of which face we're looking at.
-        if let [Some(f0), Some(f1)] = [
-            get_child_cell_id(face.cells[0], o[1]).0,
-            get_child_cell_id(face.cells[1], o[0]).0,
-        ] {
-            octree.face_stack.push(Face {
-                axis: face.axis,
-                cells: [f0, f1],
-            });
-        }
-    }
-
+    // The child faces are searched in order, and then the edges from last to
+    // first.
     for edge_i in 0..4 {
         let o = FACE_TO_EDGE_ADJACENT_OCTANTS[face.axis][edge_i];
 
@@ -176,11 +418,26 @@ fn contour_face_interior(octree: &mut CellOctree, face: Face) {
         let edge_axis = FACE_TO_EDGE_AXIS[face.axis][edge_i];
 
         if let [Some(e0), Some(e1), Some(e2), Some(e3)] = next_edge {
-            octree.edge_stack.push(Edge {
+            stack.push(ContourWork::Edge(Edge {
                 axis: edge_axis,
                 cells: [e0, e1, e2, e3],
                 is_duplicate: child_is_dup,
-            });
+            }));
+        }
+    }
+
+    for face_i in (0..4).rev() {
+        let o = FACE_ADJACENT_OCTANTS[face.axis][face_i];
+
+        // Mirror permutation is independent of which face we're looking at.
+        if let [Some(f0), Some(f1)] = [
+            get_child_cell_id(face.cells[0], o[1]).0,
+            get_child_cell_id(face.cells[1], o[0]).0,
+        ] {
+            stack.push(ContourWork::Face(Face {
+                axis: face.axis,
+                cells: [f0, f1],
+            }));
         }
     }
 }
@@ -188,10 +445,10 @@ fn contour_face_interior(octree: &mut CellOctree, face: Face) {
 // 2 edges
 #[inline]
 fn contour_edge_interior(
-    octree: &mut CellOctree,
+    cells: &[Cell],
+    stack: &mut Vec<ContourWork>,
     edge: Edge,
-    visit_quad: &mut impl FnMut([CellId; 4]),
-    visit_triangle: &mut impl FnMut([CellId; 3]),
+    visitor: &mut impl ContourVisitor,
 ) {
     // PRECONDITION: `edge` nodes are given in Z order.
     //
@@ -199,13 +456,13 @@ fn contour_edge_interior(
     //     e2 e3    10 11
 
     let axis = edge.axis;
-    let edge_cells = edge.cells.map(|i| &octree.all_cells[i as usize]);
+    let edge_cells = edge.cells.map(|i| &cells[i as usize]);
     if edge_cells.iter().all(|c| c.is_leaf) {
-        visit_leaf_edge(edge, edge_cells, axis, visit_quad, visit_triangle);
+        visit_leaf_edge(edge, edge_cells, visitor);
     } else {
         // We must continue bisecting this edge.
-        let mut get_child_cell_id = |parent: CellId, octant: u8| -> Option<CellId> {
-            let cell = &mut octree.all_cells[parent as usize];
+        let get_child_cell_id = |parent: CellId, octant: u8| -> Option<CellId> {
+            let cell = &cells[parent as usize];
             if cell.is_leaf {
                 // Continue participation.
                 Some(parent)
@@ -236,11 +493,11 @@ fn contour_edge_interior(
             let next_edge = [0, 1, 2, 3].map(|i| get_child_cell_id(edge.cells[i], o[3 - i]));
 
             if let [Some(e0), Some(e1), Some(e2), Some(e3)] = next_edge {
-                octree.edge_stack.push(Edge {
+                stack.push(ContourWork::Edge(Edge {
                     axis,
                     cells: [e0, e1, e2, e3],
                     is_duplicate: [false; 4],
-                });
+                }));
             }
         }
     }
@@ -249,12 +506,28 @@ fn contour_edge_interior(
 fn visit_leaf_edge(
     edge: Edge,
     edge_cells: [&Cell; 4],
-    axis: usize,
-    visit_quad: &mut impl FnMut([CellId; 4]),
-    visit_triangle: &mut impl FnMut([CellId; 3]),
+    visitor: &mut impl ContourVisitor,
 ) {
     // Check if this leaf edge is bipolar. We can just check the samples on
     // the smallest cell.
+    let mut minimal_edge = minimal_edge(edge, edge_cells);
+    let [d0, d1] = minimal_edge.samples;
+    let flip = match (d0 < 0.0, d1 < 0.0) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => return, // Not a bipolar edge.
+    };
+
+    let min_cell = edge.cells.iter().position(|&c| c == minimal_edge.cell_id);
+    let gradient = trilinear_gradient(edge_cells[min_cell.unwrap()], minimal_edge.crossing());
+    minimal_edge.normal = gradient.normalize_or_zero();
+
+    visitor.visit_minimal_edge(&minimal_edge);
+    visit_edge_polygon(&edge, flip, visitor);
+}
+
+/// The edge of the smallest of the 4 leaves around `edge`.
+pub(crate) fn minimal_edge(edge: Edge, edge_cells: [&Cell; 4]) -> MinimalEdge {
     let mut min_cell = 0;
     let mut max_depth = 0;
     for (i, cell) in edge_cells.iter().enumerate() {
@@ -264,20 +537,53 @@ fn visit_leaf_edge(
         }
     }
     // Select the edge at the opposite corner of the octant.
-    let octants = EDGE_ADJACENT_OCTANTS[axis];
+    let octants = EDGE_ADJACENT_OCTANTS[edge.axis];
     let opposite_corner = [3, 2, 1, 0][min_cell];
     let c0 = octants[0][opposite_corner];
     let c1 = octants[1][opposite_corner];
-    let sdf = &edge_cells[min_cell].samples;
-    let d0 = sdf[c0 as usize];
-    let d1 = sdf[c1 as usize];
+    let cell = edge_cells[min_cell];
 
-    let flip = match (d0 < 0.0, d1 < 0.0) {
-        (true, false) => true,
-        (false, true) => false,
-        _ => return, // Not a bipolar edge.
+    let corner_position = |corner: u8| {
+        let bits = Vec3A::new(
+            (corner & 1) as f32,
+            (corner >> 1 & 1) as f32,
+            (corner >> 2 & 1) as f32,
+        );
+        cell.extent.minimum + bits * cell.extent.shape
     };
+    MinimalEdge {
+        edge,
+        cell_id: edge.cells[min_cell],
+        corners: [c0, c1],
+        endpoints: [corner_position(c0), corner_position(c1)],
+        samples: [cell.samples[c0 as usize], cell.samples[c1 as usize]],
+        // Only needed for bipolar edges. See `visit_leaf_edge`.
+        normal: Vec3A::ZERO,
+    }
+}
 
+/// Gradient at `p` of the trilinear interpolation of the corner samples of
+/// `cell`.
+fn trilinear_gradient(cell: &Cell, p: Vec3A) -> Vec3A {
+    let t = ((p - cell.extent.minimum) / cell.extent.shape).clamp(Vec3A::ZERO, Vec3A::ONE);
+    let mut gradient = Vec3A::ZERO;
+    for (corner, &sample) in cell.samples.iter().enumerate() {
+        // Weight of this corner along each axis, and its derivative.
+        let bits = Vec3A::new(
+            (corner & 1) as f32,
+            (corner >> 1 & 1) as f32,
+            (corner >> 2 & 1) as f32,
+        );
+        let w = bits * t + (Vec3A::ONE - bits) * (Vec3A::ONE - t);
+        let dw = 2.0 * bits - Vec3A::ONE;
+        gradient += sample * Vec3A::new(dw.x * w.y * w.z, w.x * dw.y * w.z, w.x * w.y * dw.z);
+    }
+    gradient / cell.extent.shape
+}
+
+/// Visit the polygon around a bipolar edge. `flip` if the SDF increases along
+/// the edge.
+pub(crate) fn visit_edge_polygon(edge: &Edge, flip: bool, visitor: &mut impl ContourVisitor) {
     // Filter triangles with duplicate vertices (from edges with duplicate
     // cells). Because the triangles must share a diagonal, we know a
     // duplicate can't occur in both triangles. We also know that if any
@@ -296,16 +602,305 @@ fn visit_leaf_edge(
         };
         if flip {
             let flipped_tri = [use_tri[0], use_tri[2], use_tri[1]];
-            visit_triangle(flipped_tri.map(|i| edge.cells[i]));
+            visitor.visit_triangle(flipped_tri.map(|i| edge.cells[i]));
         } else {
-            visit_triangle(use_tri.map(|i| edge.cells[i]));
+            visitor.visit_triangle(use_tri.map(|i| edge.cells[i]));
         }
     } else {
         // No degenerate triangles found.
         if flip {
-            visit_quad([edge.cells[2], edge.cells[3], edge.cells[0], edge.cells[1]]);
+            visitor.visit_quad([edge.cells[2], edge.cells[3], edge.cells[0], edge.cells[1]]);
         } else {
-            visit_quad(edge.cells);
+            visitor.visit_quad(edge.cells);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives;
+
+    /// The quads and triangles of `octree`, sorted.
+    type Polygons = (Vec<[CellId; 4]>, Vec<[CellId; 3]>);
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let sdf = |p: Vec3A| {
+            sdf_primitives::cube(Vec3A::splat(0.5), p).min(sdf_primitives::sphere(0.4, p - 0.4))
+        };
+        CellOctree::build(root, 5, 0.01, 0.1, sdf).unwrap()
+    }
+
+    fn sorted((mut quads, mut triangles): Polygons) -> Polygons {
+        quads.sort_unstable();
+        triangles.sort_unstable();
+        (quads, triangles)
+    }
+
+    /// Records the leaves and polygons in the order they're visited.
+    #[derive(Default)]
+    struct Visits {
+        leaves: Vec<CellId>,
+        polygons: Vec<Vec<CellId>>,
+    }
+
+    impl ContourVisitor for Visits {
+        fn visit_leaf(&mut self, cell_id: CellId, _cell: &mut Cell) {
+            self.leaves.push(cell_id);
+        }
+
+        fn visit_quad(&mut self, cells: [CellId; 4]) {
+            self.polygons.push(cells.to_vec());
+        }
+
+        fn visit_triangle(&mut self, cells: [CellId; 3]) {
+            self.polygons.push(cells.to_vec());
+        }
+    }
+
+    /// Contour by consuming all cells, then all faces, then all edges from
+    /// separate stacks, like the traversal before the single work stack.
+    fn three_stack_polygons(octree: &CellOctree) -> Vec<Vec<CellId>> {
+        let cells = &octree.all_cells;
+        let mut visitor = Visits::default();
+        let mut cell_stack = vec![octree.root_id];
+        let (mut face_stack, mut edge_stack) = (Vec::new(), Vec::new());
+
+        while let Some(cell_id) = cell_stack.pop() {
+            let cell = &cells[cell_id as usize];
+            if cell.is_leaf {
+                continue;
+            }
+            cell_stack.extend(cell.children.iter().flatten());
+            for axis in 0..3 {
+                for octants in FACE_ADJACENT_OCTANTS[axis] {
+                    if let [Some(f0), Some(f1)] = octants.map(|o| cell.children[o as usize]) {
+                        face_stack.push(Face {
+                            axis,
+                            cells: [f0, f1],
+                        });
+                    }
+                }
+                for octants in EDGE_ADJACENT_OCTANTS[axis] {
+                    let edge_cells = octants.map(|o| cell.children[o as usize]);
+                    if let [Some(e0), Some(e1), Some(e2), Some(e3)] = edge_cells {
+                        edge_stack.push(Edge {
+                            axis,
+                            cells: [e0, e1, e2, e3],
+                            is_duplicate: [false; 4],
+                        });
+                    }
+                }
+            }
+        }
+
+        // The child in `octant`, or the cell itself if it's a leaf.
+        let child = |parent: CellId, octant: u8| {
+            let cell = &cells[parent as usize];
+            if cell.is_leaf {
+                (Some(parent), true)
+            } else {
+                (cell.children[octant as usize], false)
+            }
+        };
+
+        while let Some(face) = face_stack.pop() {
+            if face.cells.iter().all(|&c| cells[c as usize].is_leaf) {
+                continue;
+            }
+            for o in FACE_ADJACENT_OCTANTS[face.axis] {
+                if let [(Some(f0), _), (Some(f1), _)] =
+                    [child(face.cells[0], o[1]), child(face.cells[1], o[0])]
+                {
+                    face_stack.push(Face {
+                        axis: face.axis,
+                        cells: [f0, f1],
+                    });
+                }
+            }
+            for edge_i in 0..4 {
+                let o = FACE_TO_EDGE_ADJACENT_OCTANTS[face.axis][edge_i];
+                let order = FACE_TO_EDGE_NODE_ORDERS[edge_i];
+                let mirror = FACE_TO_EDGE_MIRRORS[edge_i];
+                let next_edge = [0, 1, 2, 3].map(|i| child(face.cells[order[i]], o[mirror[i]]));
+                if let [(Some(e0), d0), (Some(e1), d1), (Some(e2), d2), (Some(e3), d3)] = next_edge
+                {
+                    edge_stack.push(Edge {
+                        axis: FACE_TO_EDGE_AXIS[face.axis][edge_i],
+                        cells: [e0, e1, e2, e3],
+                        is_duplicate: [d0, d1, d2, d3],
+                    });
+                }
+            }
+        }
+
+        while let Some(edge) = edge_stack.pop() {
+            let edge_cells = edge.cells.map(|c| &cells[c as usize]);
+            if edge_cells.iter().all(|c| c.is_leaf) {
+                visit_leaf_edge(edge, edge_cells, &mut visitor);
+                continue;
+            }
+            for o in EDGE_ADJACENT_OCTANTS[edge.axis] {
+                let next_edge = [0, 1, 2, 3].map(|i| child(edge.cells[i], o[3 - i]).0);
+                if let [Some(e0), Some(e1), Some(e2), Some(e3)] = next_edge {
+                    edge_stack.push(Edge {
+                        axis: edge.axis,
+                        cells: [e0, e1, e2, e3],
+                        is_duplicate: [false; 4],
+                    });
+                }
+            }
+        }
+
+        visitor.polygons
+    }
+
+    /// Records each minimal edge with the polygon that follows it.
+    #[derive(Default)]
+    struct EdgePolygons {
+        edge: Option<MinimalEdge>,
+        polygons: Vec<(MinimalEdge, Vec<CellId>)>,
+    }
+
+    impl ContourVisitor for EdgePolygons {
+        fn visit_leaf(&mut self, _cell_id: CellId, _cell: &mut Cell) {}
+
+        fn visit_quad(&mut self, cells: [CellId; 4]) {
+            // Z order into a cycle.
+            let cycle = [cells[0], cells[2], cells[3], cells[1]];
+            let edge = self.edge.take().unwrap();
+            self.polygons.push((edge, cycle.to_vec()));
+        }
+
+        fn visit_triangle(&mut self, cells: [CellId; 3]) {
+            let edge = self.edge.take().unwrap();
+            self.polygons.push((edge, cells.to_vec()));
+        }
+
+        fn visit_minimal_edge(&mut self, edge: &MinimalEdge) {
+            assert!(self.edge.replace(*edge).is_none());
+        }
+    }
+
+    #[test]
+    fn minimal_edges_orient_polygons() {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let sdf = |p| sdf_primitives::sphere(0.6, p);
+        let mut octree = CellOctree::build(root, 5, 0.0, 0.1, sdf).unwrap();
+        let mut visitor = EdgePolygons::default();
+        octree.dual_contour_with(&mut visitor);
+        assert!(!visitor.polygons.is_empty());
+
+        for (edge, polygon) in visitor.polygons {
+            let [d0, d1] = edge.samples;
+            assert!((d0 < 0.0) != (d1 < 0.0));
+            let crossing = edge.crossing();
+            let along = edge.endpoints[1] - edge.endpoints[0];
+            assert_eq!(along.abs().max_element(), along[edge.axis()]);
+            assert!(along[edge.axis()] > 0.0);
+            assert!((crossing - edge.endpoints[0]).length() <= along.length());
+
+            // The normal points out of the sphere and agrees with the sign.
+            assert!((edge.normal.length() - 1.0).abs() < 1e-5);
+            assert!(edge.normal.dot(crossing.normalize()) > 0.9);
+            assert_eq!(edge.normal[edge.axis()] > 0.0, edge.sign() > 0);
+
+            // The polygon faces `sign * axis`.
+            let p: Vec<_> = polygon
+                .iter()
+                .map(|&id| Vec3A::from(octree.all_cells[id as usize].vertex_estimate))
+                .collect();
+            let mut area = Vec3A::ZERO;
+            for i in 0..p.len() {
+                area += p[i].cross(p[(i + 1) % p.len()]);
+            }
+            assert!(area[edge.axis()] * f32::from(edge.sign()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn tiled_regions_emit_each_polygon_once() {
+        let mut octree = octree();
+        let (mut quads, mut triangles) = (Vec::new(), Vec::new());
+        octree.dual_contour(|_, _| {}, |q| quads.push(q), |t| triangles.push(t));
+        let expected = sorted((quads, triangles));
+
+        // Split on cell boundaries and between them, with tiles reaching past
+        // the root.
+        for splits in [[-1.0, 0.0, 1.0, 2.0], [-3.0, -0.3, 0.55, 3.0]] {
+            let (mut quads, mut triangles) = (Vec::new(), Vec::new());
+            for x in splits.windows(2) {
+                for y in splits.windows(2) {
+                    for z in splits.windows(2) {
+                        let min = Vec3A::new(x[0], y[0], z[0]);
+                        let max = Vec3A::new(x[1], y[1], z[1]);
+                        let region = Extent::from_min_and_lub(min, max);
+                        let (mut leaves, mut region_quads, mut region_triangles) =
+                            (Vec::new(), Vec::new(), Vec::new());
+                        octree.dual_contour_in(
+                            &region,
+                            &mut (
+                                |id, _: &mut Cell| leaves.push(id),
+                                |q| region_quads.push(q),
+                                |t| region_triangles.push(t),
+                            ),
+                        );
+
+                        // The polygons only use visited leaves.
+                        let polygon_cells = region_quads.iter().flatten();
+                        for id in polygon_cells.chain(region_triangles.iter().flatten()) {
+                            assert!(leaves.contains(id));
+                        }
+                        quads.append(&mut region_quads);
+                        triangles.append(&mut region_triangles);
+                    }
+                }
+            }
+            assert_eq!(sorted((quads, triangles)), expected);
+        }
+    }
+
+    #[test]
+    fn work_stack_matches_three_stack_traversal() {
+        let mut octree = octree();
+        let expected = three_stack_polygons(&octree);
+        assert!(expected.iter().any(|p| p.len() == 4) && expected.iter().any(|p| p.len() == 3));
+
+        let mut visits = Visits::default();
+        octree.dual_contour_with(&mut visits);
+        assert_eq!(visits.polygons, expected);
+
+        // Each leaf that isn't below a pseudo-leaf is visited once, depth
+        // first.
+        let mut expected_leaves = Vec::new();
+        let mut stack = vec![octree.root_id];
+        while let Some(id) = stack.pop() {
+            let cell = &octree.all_cells[id as usize];
+            if cell.is_leaf {
+                expected_leaves.push(id);
+            } else {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        assert_eq!(visits.leaves, expected_leaves);
+    }
+
+    #[test]
+    fn work_stack_is_bounded_by_depth() {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let max_depth = 7;
+        let sdf = |p| sdf_primitives::sphere(0.6, p);
+        let octree = CellOctree::build(root, max_depth, 0.0, 0.1, sdf).unwrap();
+
+        let mut visits = Visits::default();
+        let mut stack = vec![ContourWork::Cell(octree.root_id)];
+        let mut peak = 0;
+        while let Some(work) = stack.pop() {
+            contour_work(&octree.all_cells, &mut stack, work, &mut visits);
+            peak = peak.max(stack.len());
         }
+        assert!(peak <= 16 * (max_depth as usize + 1), "{peak}");
+        assert!(visits.polygons.len() > 100 * peak);
     }
 }
@@ -0,0 +1,273 @@
+use crate::{qef::Qef, CellId, CellOctree};
+use glam::{Vec3A, Vec3Swizzles};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Relative eigenvalue (compared to the largest) above which a direction of
+/// the normal spectrum counts as significant.
+///
+/// For two equally weighted planes meeting at angle `θ`, the relative
+/// eigenvalue is `tan²(θ/2)`, so `0.1` detects creases sharper than about 35
+/// degrees.
+pub const SHARP_FEATURE_THRESHOLD: f32 = 0.1;
+
+/// The kind of surface feature that a cell vertex lies on.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SharpFeature {
+    /// All Hermite normals are roughly parallel.
+    #[default]
+    Smooth,
+    /// The Hermite normals span a plane, so the vertex lies on a crease.
+    Edge,
+    /// The Hermite normals span all directions, so the vertex lies on a
+    /// corner where three or more surfaces meet.
+    Corner,
+}
+
+impl SharpFeature {
+    /// Classify a feature from the eigenvalues (in decreasing order) of the
+    /// normals' covariance matrix.
+    pub fn from_normal_spectrum([e0, e1, e2]: [f32; 3], threshold: f32) -> Self {
+        if e0 <= 0.0 {
+            return Self::Smooth;
+        }
+        let min_significant = threshold * e0;
+        if e2 > min_significant {
+            Self::Corner
+        } else if e1 > min_significant {
+            Self::Edge
+        } else {
+            Self::Smooth
+        }
+    }
+
+    /// Classify a feature from the exact (non-regularized) QEF of a cell.
+    pub(crate) fn from_qef(exact_qef: &Qef) -> Self {
+        Self::from_normal_spectrum(exact_qef.eigenvalues(), SHARP_FEATURE_THRESHOLD)
+    }
+
+    /// True for creases and corners.
+    pub fn is_sharp(self) -> bool {
+        !matches!(self, Self::Smooth)
+    }
+}
+
+/// An ordered chain of crease vertices traced across adjacent leaf cells.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureLine {
+    /// The leaf cells whose vertices form the polyline, in order.
+    pub cells: Vec<CellId>,
+    /// Vertex positions, parallel to `cells`.
+    pub points: Vec<Vec3A>,
+    /// Angle in radians between the normals of the two polygons meeting at
+    /// each segment (zero for a flat surface), or the largest such angle if
+    /// more polygons meet there. `dihedral_angles[i]` belongs to the segment
+    /// that starts at `points[i]`.
+    pub dihedral_angles: Vec<f32>,
+    /// If true, the last point connects back to the first, and there is one
+    /// more dihedral angle than there are segments between `points`.
+    pub is_closed: bool,
+}
+
+impl CellOctree {
+    /// Trace the crease curves of the contoured surface into polylines.
+    ///
+    /// A segment joins two sharp-feature vertices (see [`SharpFeature`]) that
+    /// share a polygon edge whose dihedral angle is at least
+    /// `min_dihedral_angle` (radians). Polylines end at corners and wherever
+    /// the chain of segments stops or branches.
+    pub fn feature_lines(&mut self, min_dihedral_angle: f32) -> Vec<FeatureLine> {
+        let mut quads = Vec::new();
+        let mut triangles = Vec::new();
+        self.dual_contour(
+            |_cell_id, _cell| {},
+            |q| quads.push(q),
+            |tri| triangles.push(tri),
+        );
+        // Reorder the Z-ordered quads into cycles with the same winding as
+        // their triangles.
+        let polygons = quads
+            .into_iter()
+            .map(|q| vec![q[0], q[2], q[3], q[1]])
+            .chain(triangles.into_iter().map(|tri| tri.to_vec()));
+
+        let cells = &self.all_cells;
+        let position = |id: CellId| Vec3A::from(cells[id as usize].vertex_estimate);
+        let is_sharp = |id: CellId| cells[id as usize].feature.is_sharp();
+
+        // Collect the normals of all polygons around each edge between sharp
+        // vertices.
+        let mut edge_normals: BTreeMap<(CellId, CellId), Vec<Vec3A>> = BTreeMap::new();
+        for polygon in polygons {
+            let normal = newell_normal(polygon.iter().map(|&id| position(id)));
+            for (i, &v0) in polygon.iter().enumerate() {
+                let v1 = polygon[(i + 1) % polygon.len()];
+                if v0 != v1 && is_sharp(v0) && is_sharp(v1) {
+                    edge_normals
+                        .entry(segment_key(v0, v1))
+                        .or_default()
+                        .push(normal);
+                }
+            }
+        }
+
+        let mut adjacency: BTreeMap<CellId, Vec<(CellId, f32)>> = BTreeMap::new();
+        for ((v0, v1), normals) in edge_normals {
+            // Boundary edges have no dihedral angle.
+            let Some(angle) = dihedral_angle(&normals) else { continue };
+            if angle >= min_dihedral_angle {
+                adjacency.entry(v0).or_default().push((v1, angle));
+                adjacency.entry(v1).or_default().push((v0, angle));
+            }
+        }
+
+        let is_endpoint = |id: CellId| {
+            adjacency[&id].len() != 2 || cells[id as usize].feature == SharpFeature::Corner
+        };
+
+        // Open polylines start and end at endpoints. Whatever remains forms
+        // closed loops.
+        let mut visited_segments = BTreeSet::new();
+        let mut lines = Vec::new();
+        let starts = adjacency.keys().filter(|&&v| is_endpoint(v));
+        for &start in starts.chain(adjacency.keys()) {
+            for &first in &adjacency[&start] {
+                if visited_segments.contains(&segment_key(start, first.0)) {
+                    continue;
+                }
+                let mut line = FeatureLine::default();
+                line.cells.push(start);
+                let (mut current, mut next) = (start, first);
+                loop {
+                    visited_segments.insert(segment_key(current, next.0));
+                    line.dihedral_angles.push(next.1);
+                    if next.0 == start {
+                        line.is_closed = true;
+                        break;
+                    }
+                    line.cells.push(next.0);
+                    current = next.0;
+                    if is_endpoint(current) {
+                        break;
+                    }
+                    let Some(&following) = adjacency[&current]
+                        .iter()
+                        .find(|(n, _)| !visited_segments.contains(&segment_key(current, *n)))
+                        else { break };
+                    next = following;
+                }
+                line.points = line.cells.iter().map(|&id| position(id)).collect();
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+}
+
+fn segment_key(v0: CellId, v1: CellId) -> (CellId, CellId) {
+    (v0.min(v1), v0.max(v1))
+}
+
+/// The largest angle between any two of the polygon `normals` around an
+/// edge, or `None` for fewer than two polygons.
+///
+/// Non-manifold edges have more than two polygons, and any pair of them may
+/// form the crease.
+fn dihedral_angle(normals: &[Vec3A]) -> Option<f32> {
+    let mut min_cos = None;
+    for (i, n0) in normals.iter().enumerate() {
+        for n1 in &normals[i + 1..] {
+            let cos = n0.dot(*n1);
+            min_cos = Some(min_cos.map_or(cos, |c: f32| c.min(cos)));
+        }
+    }
+    min_cos.map(|c| c.clamp(-1.0, 1.0).acos())
+}
+
+/// Normal of a (possibly non-planar) polygon by Newell's method.
+fn newell_normal(vertices: impl Iterator<Item = Vec3A> + Clone) -> Vec3A {
+    let next = vertices.clone().cycle().skip(1);
+    vertices
+        .zip(next)
+        .fold(Vec3A::ZERO, |sum, (p0, p1)| sum + (p0 - p1) * (p0.yzx() + p1.yzx()))
+        .yzx()
+        .normalize_or_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives;
+    use ilattice::extent::Extent;
+
+    fn classify(normals: &[Vec3A]) -> SharpFeature {
+        let qef = normals
+            .iter()
+            .map(|&n| Qef::plane(Vec3A::ZERO, n.normalize()))
+            .fold(Qef::default(), |sum, q| sum + q);
+        SharpFeature::from_qef(&qef)
+    }
+
+    #[test]
+    fn classifies_normal_spectrum() {
+        assert_eq!(classify(&[Vec3A::Y; 4]), SharpFeature::Smooth);
+        assert_eq!(
+            classify(&[Vec3A::Y, Vec3A::new(0.05, 1.0, 0.0)]),
+            SharpFeature::Smooth
+        );
+        assert_eq!(
+            classify(&[Vec3A::Y, Vec3A::Y, Vec3A::X, Vec3A::X]),
+            SharpFeature::Edge
+        );
+        assert_eq!(
+            classify(&[Vec3A::X, Vec3A::Y, Vec3A::Z]),
+            SharpFeature::Corner
+        );
+        assert_eq!(classify(&[]), SharpFeature::Smooth);
+    }
+
+    #[test]
+    fn dihedral_angle_takes_widest_pair() {
+        use std::f32::consts::{FRAC_PI_2, PI};
+
+        assert_eq!(dihedral_angle(&[]), None);
+        assert_eq!(dihedral_angle(&[Vec3A::Y]), None);
+        let angle = |normals: &[Vec3A]| dihedral_angle(normals).unwrap();
+        assert!((angle(&[Vec3A::Y, Vec3A::X]) - FRAC_PI_2).abs() < 1e-6);
+        // A non-manifold edge whose first two polygons are coplanar.
+        assert!((angle(&[Vec3A::Y, Vec3A::Y, Vec3A::X]) - FRAC_PI_2).abs() < 1e-6);
+        assert!((angle(&[Vec3A::Y, Vec3A::X, Vec3A::NEG_Y, Vec3A::X]) - PI).abs() < 1e-6);
+    }
+
+    fn feature_lines_of(sdf: impl Fn(Vec3A) -> f32) -> Vec<FeatureLine> {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let mut octree = CellOctree::build(root, 5, 0.0001, 0.1, sdf).unwrap();
+        octree.feature_lines(0.4 * std::f32::consts::PI)
+    }
+
+    #[test]
+    fn cube_has_twelve_feature_lines() {
+        let lines = feature_lines_of(|p| sdf_primitives::cube(Vec3A::splat(0.6), p));
+        assert_eq!(lines.len(), 12);
+        for line in &lines {
+            assert!(!line.is_closed);
+            for &angle in &line.dihedral_angles {
+                assert!((angle - std::f32::consts::FRAC_PI_2).abs() < 0.2, "{angle}");
+            }
+            // Each line runs along one axis between two corners.
+            let first = line.points[0];
+            let last = *line.points.last().unwrap();
+            for p in [first, last] {
+                assert!((p.abs() - Vec3A::splat(0.6)).abs().max_element() < 0.05);
+            }
+            let span = (last - first).abs();
+            assert!((span.max_element() - 1.2).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn sphere_has_no_feature_lines() {
+        let lines = feature_lines_of(|p| sdf_primitives::sphere(0.6, p));
+        assert!(lines.is_empty());
+    }
+}
@@ -27,13 +27,30 @@
 
 mod cell_octree;
 mod contour_octree;
-mod mesh;
+mod feature;
+mod linear_octree;
+mod material;
+mod neighbors;
+mod parallel_contour;
 mod qef;
+mod query;
+mod raycast;
 mod sdf;
+mod serialization;
+mod stats;
 mod tables;
 
+pub mod mesh;
 pub mod sdf_primitives;
 
 pub use cell_octree::*;
+pub use contour_octree::{ContourVisitor, MinimalEdge};
+pub use feature::*;
+pub use linear_octree::*;
+pub use material::{MaterialId, AIR};
 pub use mesh::*;
+pub use parallel_contour::ContourPolygons;
+pub use qef::Qef;
+pub use raycast::RayHit;
 pub use sdf::*;
+pub use stats::*;
@@ -0,0 +1,443 @@
+use crate::{BuildOptions, Cell, CellId, CellOctree, IsoMesh, MeshOptions};
+use glam::{IVec3, UVec3, Vec3A};
+use ilattice::extent::Extent;
+use std::ops::Deref;
+
+/// The path from the root to a cell, as a Morton code of the cell's integer
+/// coordinates at its level, prefixed with a 1 bit to encode the level.
+///
+/// Codes sort by level, then in Z order within each level.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LocationCode(pub u64);
+
+impl LocationCode {
+    pub const ROOT: Self = Self(1);
+    pub const MAX_LEVEL: u8 = 21;
+
+    /// # Panics
+    ///
+    /// If `level > MAX_LEVEL` or `coords` are not within `[0, 2^level)`.
+    pub fn new(level: u8, coords: UVec3) -> Self {
+        assert!(level <= Self::MAX_LEVEL);
+        assert!(coords.max_element() < 1 << level);
+        let mut code = 1;
+        for bit in (0..level).rev() {
+            let octant =
+                (coords.x >> bit & 1) | (coords.y >> bit & 1) << 1 | (coords.z >> bit & 1) << 2;
+            code = code << 3 | octant as u64;
+        }
+        Self(code)
+    }
+
+    pub fn level(self) -> u8 {
+        ((63 - self.0.leading_zeros()) / 3) as u8
+    }
+
+    /// Integer coordinates of the cell within the grid of its level.
+    pub fn coords(self) -> UVec3 {
+        let mut coords = UVec3::ZERO;
+        for bit in 0..self.level() {
+            let octant = (self.0 >> (3 * bit)) as u32;
+            coords += UVec3::new(octant & 1, octant >> 1 & 1, octant >> 2 & 1) << bit;
+        }
+        coords
+    }
+
+    pub fn parent(self) -> Option<Self> {
+        (self != Self::ROOT).then_some(Self(self.0 >> 3))
+    }
+
+    /// # Panics
+    ///
+    /// If this cell is at `MAX_LEVEL`.
+    pub fn child(self, octant: u8) -> Self {
+        assert!(self.level() < Self::MAX_LEVEL);
+        Self(self.0 << 3 | (octant & 0b111) as u64)
+    }
+}
+
+/// A sorted view of a [`CellOctree`]: the same cells, sorted by
+/// [`LocationCode`], so any cell or neighbor can be found by its level and
+/// coordinates in `O(log n)`.
+///
+/// The octree is built as usual and then sorted in place, so building needs
+/// only the codes and an index per cell beyond the [`CellOctree`] itself.
+///
+/// Dereferences to the underlying [`CellOctree`] for queries.
+#[derive(Debug, Default)]
+pub struct LinearOctree {
+    octree: CellOctree,
+    /// Parallel to `octree.all_cells` and sorted.
+    codes: Vec<LocationCode>,
+}
+
+impl LinearOctree {
+    /// Like [`CellOctree::build_with_options`].
+    ///
+    /// # Panics
+    ///
+    /// If `options.max_depth > LocationCode::MAX_LEVEL`.
+    pub fn build(
+        root_cell: Extent<Vec3A>,
+        options: &BuildOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> Option<Self> {
+        assert!(options.max_depth <= LocationCode::MAX_LEVEL);
+        CellOctree::build_with_options(root_cell, options, sdf).map(Self::from_cell_octree)
+    }
+
+    /// Sort the cells of `octree` in place by location code. This changes all
+    /// [`CellId`]s.
+    ///
+    /// # Panics
+    ///
+    /// If `octree` is deeper than `LocationCode::MAX_LEVEL`.
+    pub fn from_cell_octree(mut octree: CellOctree) -> Self {
+        if octree.all_cells.is_empty() {
+            return Self::default();
+        }
+
+        // Assign codes from the root down. Every cell is a descendant of the
+        // root, even those below pseudo-leaves.
+        let mut coded = Vec::with_capacity(octree.all_cells.len());
+        let mut stack = vec![(octree.root_id, LocationCode::ROOT)];
+        while let Some((cell_id, code)) = stack.pop() {
+            coded.push((code, cell_id));
+            let cell = &octree.all_cells[cell_id as usize];
+            for (octant, child) in cell.children.iter().enumerate() {
+                if let Some(child) = child {
+                    stack.push((*child, code.child(octant as u8)));
+                }
+            }
+        }
+        debug_assert_eq!(coded.len(), octree.all_cells.len());
+        coded.sort_unstable();
+
+        let mut old_to_new = vec![0; coded.len()];
+        for (new, &(_, old)) in coded.iter().enumerate() {
+            old_to_new[old as usize] = new as CellId;
+        }
+        for cell in &mut octree.all_cells {
+            cell.parent = cell.parent.map(|p| old_to_new[p as usize]);
+            for child in cell.children.iter_mut().flatten() {
+                *child = old_to_new[*child as usize];
+            }
+        }
+        permute(&mut octree.all_cells, &old_to_new);
+        if !octree.qefs.is_empty() {
+            permute(&mut octree.qefs, &old_to_new);
+        }
+        if !octree.corner_materials.is_empty() {
+            permute(&mut octree.corner_materials, &old_to_new);
+        }
+        octree.root_id = old_to_new[octree.root_id as usize];
+
+        Self {
+            octree,
+            codes: coded.into_iter().map(|(code, _)| code).collect(),
+        }
+    }
+
+    pub fn into_cell_octree(self) -> CellOctree {
+        self.octree
+    }
+
+    /// Location codes of all cells, parallel to [`CellOctree::all_cells`].
+    pub fn codes(&self) -> &[LocationCode] {
+        &self.codes
+    }
+
+    pub fn find_code(&self, code: LocationCode) -> Option<CellId> {
+        self.codes.binary_search(&code).ok().map(|i| i as CellId)
+    }
+
+    /// Find the cell at `level` with integer `coords` in the grid of that
+    /// level.
+    pub fn find(&self, level: u8, coords: UVec3) -> Option<CellId> {
+        if level > LocationCode::MAX_LEVEL || coords.max_element() >= 1 << level {
+            return None;
+        }
+        self.find_code(LocationCode::new(level, coords))
+    }
+
+    pub fn get(&self, level: u8, coords: UVec3) -> Option<&Cell> {
+        self.find(level, coords)
+            .map(|id| &self.octree.all_cells[id as usize])
+    }
+
+    /// Like [`CellOctree::face_neighbor`], but found by location code.
+    pub fn face_neighbor(&self, cell_id: CellId, axis: usize, positive: bool) -> Option<CellId> {
+        let mut direction = [0; 3];
+        direction[axis] = if positive { 1 } else { -1 };
+        self.neighbor(cell_id, direction)
+    }
+
+    /// Like [`CellOctree::edge_neighbors`], but found by location code.
+    pub fn edge_neighbors(
+        &self,
+        cell_id: CellId,
+        axis: usize,
+        positive: [bool; 2],
+    ) -> [Option<CellId>; 3] {
+        let [a1, a2] = [(axis + 1) % 3, (axis + 2) % 3];
+        let [a1, a2] = [a1.min(a2), a1.max(a2)];
+        let [s1, s2] = positive.map(|p| if p { 1 } else { -1 });
+
+        let mut d1 = [0; 3];
+        d1[a1] = s1;
+        let mut d2 = [0; 3];
+        d2[a2] = s2;
+        let mut diagonal = d1;
+        diagonal[a2] = s2;
+
+        [
+            self.neighbor(cell_id, d1),
+            self.neighbor(cell_id, d2),
+            self.neighbor(cell_id, diagonal),
+        ]
+    }
+
+    /// Find the same-size or coarser cell that is offset from `cell_id` by one
+    /// cell width along each axis where `direction` is nonzero.
+    ///
+    /// Looks up the ancestors of the offset code from the root down, stopping
+    /// at the first leaf.
+    fn neighbor(&self, cell_id: CellId, direction: [i8; 3]) -> Option<CellId> {
+        let code = self.codes[cell_id as usize];
+        let level = code.level();
+        let coords = code.coords().as_ivec3() + IVec3::from(direction.map(i32::from));
+        if coords.min_element() < 0 || coords.max_element() >= 1 << level {
+            return None;
+        }
+        let target = LocationCode::new(level, coords.as_uvec3());
+
+        for ancestor_level in 0..=level {
+            let id = self.find_code(LocationCode(target.0 >> (3 * (level - ancestor_level))))?;
+            if ancestor_level == level || self.octree.all_cells[id as usize].is_leaf {
+                return Some(id);
+            }
+        }
+        unreachable!()
+    }
+
+    /// See [`CellOctree::compact`]. Cells stay sorted.
+    pub fn compact(&mut self) -> Vec<Option<CellId>> {
+        let old_to_new = self.octree.compact();
+        let mut kept = old_to_new.iter();
+        self.codes.retain(|_| kept.next().unwrap().is_some());
+        old_to_new
+    }
+
+    /// See [`CellOctree::dual_contour`].
+    pub fn dual_contour(
+        &mut self,
+        visit_leaf_cell: impl FnMut(CellId, &mut Cell),
+        visit_quad: impl FnMut([CellId; 4]),
+        visit_triangle: impl FnMut([CellId; 3]),
+    ) {
+        self.octree
+            .dual_contour(visit_leaf_cell, visit_quad, visit_triangle)
+    }
+
+    /// See [`CellOctree::contour_to_mesh`].
+    pub fn contour_to_mesh(
+        &mut self,
+        options: &MeshOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> IsoMesh {
+        self.octree.contour_to_mesh(options, sdf)
+    }
+}
+
+/// Move `items[i]` to `items[old_to_new[i]]` for all `i`, following the cycles
+/// of the permutation.
+fn permute<T>(items: &mut [T], old_to_new: &[CellId]) {
+    let mut old_to_new = old_to_new.to_vec();
+    for i in 0..items.len() {
+        while old_to_new[i] as usize != i {
+            let j = old_to_new[i] as usize;
+            items.swap(i, j);
+            old_to_new.swap(i, j);
+        }
+    }
+}
+
+impl Deref for LinearOctree {
+    type Target = CellOctree;
+
+    fn deref(&self) -> &Self::Target {
+        &self.octree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives;
+
+    fn sdf(p: Vec3A) -> f32 {
+        sdf_primitives::cube(Vec3A::splat(0.5), p).min(sdf_primitives::sphere(0.4, p - 0.4))
+    }
+
+    fn root() -> Extent<Vec3A> {
+        Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0))
+    }
+
+    fn options() -> BuildOptions {
+        BuildOptions {
+            max_depth: 5,
+            error_tolerance: 0.01,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn location_code_round_trips() {
+        assert_eq!(LocationCode::new(0, UVec3::ZERO), LocationCode::ROOT);
+        let max = LocationCode::MAX_LEVEL;
+        for (level, coords) in [
+            (1, UVec3::new(1, 0, 1)),
+            (3, UVec3::new(5, 2, 7)),
+            (10, UVec3::new(1023, 0, 517)),
+            (max, UVec3::new((1 << max) - 1, 12345, 1 << (max - 1))),
+        ] {
+            let code = LocationCode::new(level, coords);
+            assert_eq!(code.level(), level);
+            assert_eq!(code.coords(), coords);
+
+            let parent = code.parent().unwrap();
+            assert_eq!(parent.level(), level - 1);
+            assert_eq!(parent.coords(), coords >> 1);
+            let octant = (coords.x & 1) | (coords.y & 1) << 1 | (coords.z & 1) << 2;
+            assert_eq!(parent.child(octant as u8), code);
+        }
+        assert_eq!(LocationCode::ROOT.parent(), None);
+    }
+
+    #[test]
+    fn location_codes_sort_by_level_then_z_order() {
+        let mut codes = Vec::new();
+        for level in 0..=2 {
+            let n = 1 << level;
+            for z in 0..n {
+                for y in 0..n {
+                    for x in 0..n {
+                        codes.push(LocationCode::new(level, UVec3::new(x, y, z)));
+                    }
+                }
+            }
+        }
+        codes.sort();
+        for pair in codes.windows(2) {
+            assert!(pair[0].level() <= pair[1].level());
+        }
+        // Within a level, the 8 children of each parent are contiguous and in
+        // octant order.
+        let level_2: Vec<_> = codes.iter().filter(|c| c.level() == 2).collect();
+        for (i, code) in level_2.iter().enumerate() {
+            assert_eq!(code.parent().unwrap().0, 8 + i as u64 / 8);
+            assert_eq!(code.0 & 0b111, i as u64 % 8);
+        }
+    }
+
+    #[test]
+    fn finds_cells_by_level_and_coords() {
+        let octree = LinearOctree::build(root(), &options(), sdf).unwrap();
+        assert!(octree.codes().windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(octree.find_code(LocationCode::ROOT), Some(octree.root_id));
+
+        for (id, (cell, code)) in octree.all_cells.iter().zip(octree.codes()).enumerate() {
+            assert_eq!(cell.depth, code.level());
+            let size = 2.0 / (1 << cell.depth) as f32;
+            let expected_min = Vec3A::splat(-1.0) + code.coords().as_vec3a() * size;
+            assert!((cell.extent.minimum - expected_min).length() < 1e-5);
+            assert_eq!(octree.find(code.level(), code.coords()), Some(id as CellId));
+            if let Some(parent) = cell.parent {
+                assert_eq!(octree.codes()[parent as usize], code.parent().unwrap());
+            }
+        }
+        assert_eq!(octree.find(2, UVec3::new(4, 0, 0)), None);
+        assert_eq!(octree.find(LocationCode::MAX_LEVEL + 1, UVec3::ZERO), None);
+    }
+
+    /// The cells that aren't below a leaf.
+    fn reachable_cells(octree: &CellOctree) -> Vec<CellId> {
+        let mut cells = Vec::new();
+        let mut stack = vec![octree.root_id];
+        while let Some(id) = stack.pop() {
+            cells.push(id);
+            let cell = &octree.all_cells[id as usize];
+            if !cell.is_leaf {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn neighbors_match_cell_octree() {
+        let octree = LinearOctree::build(root(), &options(), sdf).unwrap();
+        let cell_octree: &CellOctree = &octree;
+        let (mut found, mut coarser) = (0, 0);
+        for cell_id in reachable_cells(&octree) {
+            let depth = octree.all_cells[cell_id as usize].depth;
+            for axis in 0..3 {
+                for positive in [false, true] {
+                    let neighbor = octree.face_neighbor(cell_id, axis, positive);
+                    assert_eq!(neighbor, cell_octree.face_neighbor(cell_id, axis, positive));
+                    if let Some(n) = neighbor {
+                        found += 1;
+                        coarser += (octree.all_cells[n as usize].depth < depth) as usize;
+                    }
+                }
+                for positive in [[false, false], [false, true], [true, false], [true, true]] {
+                    assert_eq!(
+                        octree.edge_neighbors(cell_id, axis, positive),
+                        cell_octree.edge_neighbors(cell_id, axis, positive)
+                    );
+                }
+            }
+        }
+        assert!(found > 0 && coarser > 0);
+    }
+
+    #[test]
+    fn sorting_keeps_qefs_with_cells() {
+        let options = BuildOptions {
+            store_qefs: true,
+            ..options()
+        };
+        let cell_octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+        let octree = LinearOctree::build(root(), &options, sdf).unwrap();
+        assert_eq!(octree.all_cells.len(), cell_octree.all_cells.len());
+        for (old_id, old_cell) in cell_octree.all_cells.iter().enumerate() {
+            let size = 2.0 / (1 << old_cell.depth) as f32;
+            let coords = ((old_cell.extent.minimum + 1.0) / size).round().as_uvec3();
+            let new_id = octree.find(old_cell.depth, coords).unwrap() as usize;
+            assert_eq!(octree.all_cells[new_id].extent, old_cell.extent);
+            assert_eq!(octree.qefs[new_id], cell_octree.qefs[old_id]);
+        }
+    }
+
+    #[test]
+    fn contours_like_cell_octree() {
+        let mesh_options = MeshOptions::default();
+        let mut cell_octree = CellOctree::build_with_options(root(), &options(), sdf).unwrap();
+        let expected = cell_octree.contour_to_mesh(&mesh_options, sdf);
+
+        let mut octree = LinearOctree::build(root(), &options(), sdf).unwrap();
+        let actual = octree.contour_to_mesh(&mesh_options, sdf);
+        assert_eq!(actual.positions, expected.positions);
+        assert_eq!(actual.tri_indices, expected.tri_indices);
+
+        let old_to_new = octree.compact();
+        assert!(old_to_new.contains(&None));
+        assert!(octree.codes().windows(2).all(|pair| pair[0] < pair[1]));
+        for (code, cell) in octree.codes().iter().zip(&octree.all_cells) {
+            assert_eq!(code.level(), cell.depth);
+        }
+        let compacted = octree.contour_to_mesh(&mesh_options, sdf);
+        assert_eq!(compacted.positions, expected.positions);
+        assert_eq!(compacted.tri_indices, expected.tri_indices);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    branch_empty_check, central_gradient, qef::Qef, BuildOptions, BuildReport, Cell, CellId,
+    CellOctree, SharpFeature,
+};
+use glam::{Vec3, Vec3A};
+use ilattice::extent::Extent;
+
+/// Identifies the material at a sample.
+///
+/// Material `i + 1` is given by the SDF at index `i` of the slice passed to
+/// [`CellOctree::build_multi_material`].
+pub type MaterialId = u8;
+
+/// The material of samples outside of every solid material.
+pub const AIR: MaterialId = 0;
+
+impl CellOctree {
+    /// Build an octree that resolves the boundaries between several solid
+    /// materials, each given by an SDF, as well as their boundary with
+    /// [`AIR`].
+    ///
+    /// A point belongs to the material whose SDF is smallest there, if that
+    /// is negative, otherwise to air. To embed one material in another,
+    /// subtract it from the outer one with `outer.max(-inner)`.
+    ///
+    /// Each leaf's vertex minimizes the QEF of every material boundary
+    /// crossing its edges, so it lands on the junction where several
+    /// materials meet. Contour the result with [`Self::contour_materials`].
+    ///
+    /// Only [`BuildOptions::max_depth`], [`BuildOptions::precision`] and
+    /// [`BuildOptions::store_qefs`] apply. The octree is not simplified, so
+    /// all leaves are at the maximum depth. Cell samples are the union of the
+    /// material SDFs, so [`Self::dual_contour`] still gives the boundary with
+    /// air.
+    ///
+    /// # Panics
+    ///
+    /// If `materials` is empty, or has [`MaterialId::MAX`] or more SDFs.
+    pub fn build_multi_material(
+        root_cell: Extent<Vec3A>,
+        options: &BuildOptions,
+        materials: &[&dyn Fn(Vec3A) -> f32],
+    ) -> Option<Self> {
+        let materials = Materials::new(materials);
+        let mut me = Self::default();
+        let root_id = me.build_material_cell(options, &materials, root_cell, 0)?;
+
+        let corner_materials = std::mem::take(&mut me.corner_materials);
+        let mut octree = Self::new(root_id, me.all_cells, me.qefs);
+        octree.corner_materials = corner_materials;
+        octree.build_report = Some(BuildReport {
+            sdf_evaluations: materials.evaluations.get(),
+            ..Default::default()
+        });
+        Some(octree)
+    }
+
+    /// The material at each corner of every cell, parallel to
+    /// [`Self::all_cells`], if the octree was built with
+    /// [`Self::build_multi_material`].
+    pub fn corner_materials(&self) -> Option<&[[MaterialId; 8]]> {
+        (!self.corner_materials.is_empty()).then_some(self.corner_materials.as_slice())
+    }
+
+    fn build_material_cell(
+        &mut self,
+        options: &BuildOptions,
+        materials: &Materials,
+        extent: Extent<Vec3A>,
+        depth: u8,
+    ) -> Option<CellId> {
+        let nearest = extent.corners3().map(|p| materials.nearest_two(p));
+        let corner_materials = nearest.map(|[(m, _), _]| m);
+        let is_mixed = corner_materials.iter().any(|&m| m != corner_materials[0]);
+
+        let is_leaf = depth == options.max_depth;
+        if is_leaf && !is_mixed {
+            return None;
+        }
+        // Half the gap between the two smallest fields is as Lipschitz as the
+        // SDFs, and the material can only change where it's zero.
+        let half_gaps = nearest.map(|[(_, f1), (_, f2)]| 0.5 * (f2 - f1));
+        if !is_mixed && branch_empty_check(extent.shape.length(), &half_gaps) {
+            return None;
+        }
+
+        let mut cell = Cell {
+            extent,
+            samples: nearest.map(|[(m, f), _]| if m == AIR { -f } else { f }),
+            children: [None; 8],
+            parent: None,
+            vertex_estimate: Vec3::ZERO,
+            qef_error: 0.0,
+            feature: SharpFeature::Smooth,
+            depth,
+            is_leaf,
+        };
+
+        let (regularized_qef, exact_qef) = if is_leaf {
+            let qefs = materials.boundary_qefs(&extent, &corner_materials, options.precision);
+            if qefs.0 == Qef::default() {
+                // No boundary normals could be estimated.
+                cell.vertex_estimate = extent.center().into();
+            } else {
+                cell.estimate_vertex_with_qef(&qefs.0, &qefs.1);
+            }
+            qefs
+        } else {
+            let mut any_children = false;
+            for (child_extent, child_id) in extent
+                .split3(extent.center())
+                .into_iter()
+                .zip(&mut cell.children)
+            {
+                *child_id = self.build_material_cell(options, materials, child_extent, depth + 1);
+                any_children |= child_id.is_some();
+            }
+            if !any_children {
+                return None;
+            }
+            Default::default()
+        };
+
+        let id = self.push_cell(options, cell, regularized_qef, exact_qef);
+        self.corner_materials.push(corner_materials);
+        Some(id)
+    }
+}
+
+/// The SDFs of the solid materials, counting evaluations.
+pub(crate) struct Materials<'a> {
+    sdfs: &'a [&'a dyn Fn(Vec3A) -> f32],
+    evaluations: std::cell::Cell<u64>,
+}
+
+impl<'a> Materials<'a> {
+    pub(crate) fn new(sdfs: &'a [&'a dyn Fn(Vec3A) -> f32]) -> Self {
+        assert!(!sdfs.is_empty(), "at least one material is required");
+        assert!(
+            sdfs.len() < MaterialId::MAX as usize,
+            "too many materials for MaterialId"
+        );
+        Self {
+            sdfs,
+            evaluations: std::cell::Cell::new(0),
+        }
+    }
+
+    fn eval(&self, index: usize, p: Vec3A) -> f32 {
+        self.evaluations.set(self.evaluations.get() + 1);
+        (self.sdfs[index])(p)
+    }
+
+    fn union(&self, p: Vec3A) -> f32 {
+        (0..self.sdfs.len())
+            .map(|i| self.eval(i, p))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// The field of `material`, which is smallest where that material is.
+    ///
+    /// This is the material's SDF, or the negated union of all SDFs for air.
+    pub(crate) fn field(&self, material: MaterialId, p: Vec3A) -> f32 {
+        if material == AIR {
+            -self.union(p)
+        } else {
+            self.eval(material as usize - 1, p)
+        }
+    }
+
+    /// Negative on the `back` side of the boundary between two materials and
+    /// positive on the `front` side.
+    pub(crate) fn boundary(
+        &self,
+        back: MaterialId,
+        front: MaterialId,
+    ) -> impl Fn(Vec3A) -> f32 + '_ {
+        move |p| self.field(back, p) - self.field(front, p)
+    }
+
+    /// The two materials with the smallest fields at `p`, with those fields,
+    /// in increasing order. The first is the material at `p`. Ties go to the
+    /// smaller ID, so points exactly on a solid's surface are air.
+    pub(crate) fn nearest_two(&self, p: Vec3A) -> [(MaterialId, f32); 2] {
+        let mut nearest = [(MaterialId::MAX, f32::INFINITY); 2];
+        let mut consider = |m: MaterialId, f: f32| {
+            if (f, m) < (nearest[0].1, nearest[0].0) {
+                nearest[1] = nearest[0];
+                nearest[0] = (m, f);
+            } else if (f, m) < (nearest[1].1, nearest[1].0) {
+                nearest[1] = (m, f);
+            }
+        };
+        let mut union = f32::INFINITY;
+        for i in 0..self.sdfs.len() {
+            let d = self.eval(i, p);
+            union = union.min(d);
+            consider(i as MaterialId + 1, d);
+        }
+        consider(AIR, -union);
+        nearest
+    }
+
+    /// The regularized and exact QEFs of the material boundaries crossing the
+    /// edges of a leaf.
+    fn boundary_qefs(
+        &self,
+        extent: &Extent<Vec3A>,
+        corner_materials: &[MaterialId; 8],
+        precision: f32,
+    ) -> (Qef, Qef) {
+        let mut regularized_qef = Qef::default();
+        let mut exact_qef = Qef::default();
+
+        let corners = extent.corners3();
+        for [e1, e2] in Extent::<Vec3A>::EDGES3 {
+            let (m1, m2) = (corner_materials[e1], corner_materials[e2]);
+            if m1 == m2 {
+                continue;
+            }
+            // The boundary between the two end materials is negative at e1
+            // and positive at e2.
+            let boundary = self.boundary(m1, m2);
+            let (h1, h2) = (boundary(corners[e1]), boundary(corners[e2]));
+            let t = if h1 < h2 { h1 / (h1 - h2) } else { 0.5 };
+            let edge_cross_p = corners[e1].lerp(corners[e2], t.clamp(0.0, 1.0));
+
+            let normal = central_gradient(&boundary, edge_cross_p, 0.0001).normalize_or_zero();
+            if normal == Vec3A::ZERO {
+                continue;
+            }
+
+            regularized_qef = regularized_qef
+                + Qef::isometric_probabilistic_plane(
+                    edge_cross_p,
+                    normal,
+                    precision * extent.shape.x,
+                    precision,
+                );
+            exact_qef = exact_qef + Qef::plane(edge_cross_p, normal);
+        }
+
+        (regularized_qef, exact_qef)
+    }
+}
@@ -1,8 +1,305 @@
-use glam::Vec3A;
+pub mod io;
+mod materials;
+mod shells;
+mod submeshes;
+mod tangents;
+mod uv;
+
+pub use materials::MaterialMesh;
+pub use submeshes::{MaterialAssignment, SeamPolicy, Submesh, SubmeshedMesh};
+pub use tangents::generate_tangents;
+pub use uv::UvProjection;
+
+use uv::polygon_normal;
+
+use crate::{cell_is_bipolar, central_gradient, CellId, CellOctree, ContourPolygons};
+use glam::{Vec2, Vec3A, Vec4};
 
 pub type MeshVertexId = u32;
 pub const NULL_MESH_VERTEX_ID: MeshVertexId = MeshVertexId::MAX;
 
+/// Give a vertex from `push_vertex` to each cell of `polygons` that doesn't
+/// have one in `cell_vertex_ids` yet.
+///
+/// Meshes only make vertices for the leaves whose corners straddle their
+/// surface, but a leaf collapsed by [`crate::BuildControl::StopRefining`] can
+/// be on the surface without that.
+pub(crate) fn add_missing_vertices(
+    polygons: &ContourPolygons,
+    cell_vertex_ids: &mut [MeshVertexId],
+    mut push_vertex: impl FnMut(CellId) -> MeshVertexId,
+) {
+    let quad_cells = polygons.quads.iter().flatten();
+    for &cell_id in quad_cells.chain(polygons.triangles.iter().flatten()) {
+        let vertex_id = &mut cell_vertex_ids[cell_id as usize];
+        if *vertex_id == NULL_MESH_VERTEX_ID {
+            *vertex_id = push_vertex(cell_id);
+        }
+    }
+}
+
+/// Polygon mesh extracted from a [`CellOctree`].
+#[derive(Clone, Debug, Default)]
+pub struct IsoMesh {
+    pub positions: Vec<Vec3A>,
+    pub normals: Vec<Vec3A>,
+    /// The leaf cell that generated each vertex, or `CellId::MAX` for vertices
+    /// that don't belong to a cell (see [`Triangulation::CenterVertex`]).
+    pub cell_ids: Vec<CellId>,
+    pub tri_indices: Vec<MeshVertexId>,
+    /// Only populated if [`MeshOptions::keep_quads`] is set. Each quad is a
+    /// cycle of 4 vertices with the same winding as the triangles.
+    pub quad_indices: Vec<MeshVertexId>,
+    /// Empty unless generated by [`IsoMesh::generate_uvs`] or
+    /// [`MeshOptions::uv_projection`].
+    pub uvs: Vec<Vec2>,
+    /// Empty unless generated by [`IsoMesh::generate_tangents`] or
+    /// [`MeshOptions::uv_projection`]. XYZ is the tangent direction and W is
+    /// the handedness of the bitangent.
+    pub tangents: Vec<Vec4>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshOptions {
+    /// Step size for the central differences used to estimate normals.
+    pub normal_delta: f32,
+    /// If set, run [`IsoMesh::repair_sharp_normals`] with this similarity
+    /// threshold.
+    pub sharp_normal_threshold: Option<f32>,
+    /// Output quads in [`IsoMesh::quad_indices`] instead of triangulating
+    /// them.
+    pub keep_quads: bool,
+    /// How to split quads into triangles when not keeping quads.
+    pub triangulation: Triangulation,
+    /// If set, generate UVs with this projection and then tangents, after
+    /// repairing normals.
+    pub uv_projection: Option<UvProjection>,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            normal_delta: 0.001,
+            sharp_normal_threshold: Some(0.95),
+            keep_quads: false,
+            triangulation: Triangulation::FixedDiagonal,
+            uv_projection: None,
+        }
+    }
+}
+
+/// Strategy for splitting quads into triangles.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Triangulation {
+    /// Always split along the same diagonal.
+    #[default]
+    FixedDiagonal,
+    /// Split along the shorter diagonal.
+    ShortestDiagonal,
+    /// Split along the diagonal whose triangles best agree with the vertex
+    /// normals. This keeps the fold convex where the surface is convex and
+    /// follows creases.
+    NormalConsistency,
+    /// Split into 4 triangles around a new vertex at the quad's centroid,
+    /// projected onto the surface of the SDF.
+    CenterVertex,
+}
+
+impl IsoMesh {
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.normals.clear();
+        self.cell_ids.clear();
+        self.tri_indices.clear();
+        self.quad_indices.clear();
+        self.uvs.clear();
+        self.tangents.clear();
+    }
+
+    fn push_vertex(&mut self, position: Vec3A, normal: Vec3A, cell_id: CellId) -> MeshVertexId {
+        let id = self.positions.len() as MeshVertexId;
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.cell_ids.push(cell_id);
+        id
+    }
+
+    /// Split the quad `[v0, v1, v2, v3]` (a cycle) into triangles.
+    fn triangulate_quad(
+        &mut self,
+        quad: [MeshVertexId; 4],
+        triangulation: Triangulation,
+        sdf: impl Fn(Vec3A) -> f32,
+        normal_delta: f32,
+    ) {
+        let [v0, v1, v2, v3] = quad;
+        let split_02 = [v0, v1, v2, v0, v2, v3];
+        let split_13 = [v0, v1, v3, v1, v2, v3];
+        let p = quad.map(|v| self.positions[v as usize]);
+        let tris = match triangulation {
+            Triangulation::FixedDiagonal => split_13,
+            Triangulation::ShortestDiagonal => {
+                if p[0].distance_squared(p[2]) < p[1].distance_squared(p[3]) {
+                    split_02
+                } else {
+                    split_13
+                }
+            }
+            Triangulation::NormalConsistency => {
+                let consistency = |tris: &[MeshVertexId; 6]| -> f32 {
+                    tris.chunks_exact(3)
+                        .map(|t| {
+                            let tp = [0, 1, 2].map(|i| self.positions[t[i] as usize]);
+                            let tn = (tp[1] - tp[0]).cross(tp[2] - tp[0]).normalize_or_zero();
+                            t.iter().map(|&v| tn.dot(self.normals[v as usize])).sum::<f32>()
+                        })
+                        .sum()
+                };
+                if consistency(&split_02) > consistency(&split_13) {
+                    split_02
+                } else {
+                    split_13
+                }
+            }
+            Triangulation::CenterVertex => {
+                // Take one Newton step from the centroid toward the surface.
+                let centroid = 0.25 * (p[0] + p[1] + p[2] + p[3]);
+                let grad = central_gradient(&sdf, centroid, normal_delta);
+                let grad_len2 = grad.length_squared();
+                let center = if grad_len2 > 0.0 {
+                    centroid - sdf(centroid) * grad / grad_len2
+                } else {
+                    centroid
+                };
+                let normal = central_gradient(&sdf, center, normal_delta).normalize();
+                let c = self.push_vertex(center, normal, CellId::MAX);
+                self.tri_indices
+                    .extend_from_slice(&[v0, v1, c, v1, v2, c, v2, v3, c, v3, v0, c]);
+                return;
+            }
+        };
+        self.tri_indices.extend_from_slice(&tris);
+    }
+
+    /// Add `polygons` between the vertices in `cell_vertex_ids` (indexed by
+    /// [`CellId`]), then repair normals.
+    fn add_polygons(
+        &mut self,
+        polygons: ContourPolygons,
+        cell_vertex_ids: &[MeshVertexId],
+        options: &MeshOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) {
+        let to_vertex = |cell_id: CellId| cell_vertex_ids[cell_id as usize];
+        self.tri_indices
+            .extend(polygons.triangles.into_iter().flatten().map(to_vertex));
+        for q in polygons.quads {
+            // Reorder from Z order into a cycle.
+            let quad = [q[0], q[2], q[3], q[1]].map(to_vertex);
+            if options.keep_quads {
+                self.quad_indices.extend_from_slice(&quad);
+            } else {
+                self.triangulate_quad(quad, options.triangulation, &sdf, options.normal_delta);
+            }
+        }
+
+        self.finish(options);
+    }
+
+    /// Repair normals and generate UVs and tangents, as requested by
+    /// `options`.
+    fn finish(&mut self, options: &MeshOptions) {
+        if let Some(threshold) = options.sharp_normal_threshold {
+            self.repair_sharp_normals(threshold);
+        }
+        if let Some(projection) = options.uv_projection {
+            self.generate_uvs(projection);
+            self.generate_tangents();
+        }
+    }
+
+    /// See [`repair_sharp_normals`], which this extends to quads by
+    /// comparing with the normal of the whole quad. Split vertices keep their
+    /// `cell_ids` and `uvs`, and their `tangents` are made orthogonal to the
+    /// new normal.
+    pub fn repair_sharp_normals(&mut self, normal_similarity_threshold: f32) {
+        let Self {
+            cell_ids,
+            uvs,
+            tangents,
+            ..
+        } = self;
+        let polygons = self
+            .tri_indices
+            .chunks_exact_mut(3)
+            .chain(self.quad_indices.chunks_exact_mut(4));
+        repair_sharp_normals_with(
+            normal_similarity_threshold,
+            polygons,
+            &mut self.positions,
+            &mut self.normals,
+            |v, n| {
+                let v = v as usize;
+                cell_ids.push(cell_ids[v]);
+                if !uvs.is_empty() {
+                    uvs.push(uvs[v]);
+                }
+                if !tangents.is_empty() {
+                    let t = Vec3A::from(tangents[v].truncate());
+                    let mut t = (t - n * n.dot(t)).normalize_or_zero();
+                    if t == Vec3A::ZERO {
+                        t = n.any_orthonormal_vector();
+                    }
+                    tangents.push(t.extend(tangents[v].w));
+                }
+            },
+        );
+    }
+}
+
+impl CellOctree {
+    /// Contour the octree into a polygon mesh.
+    ///
+    /// Normals are estimated from the gradient of `sdf` at each vertex, capped
+    /// like the octree's samples (see [`Self::surface_sdf`]).
+    pub fn contour_to_mesh(
+        &mut self,
+        options: &MeshOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> IsoMesh {
+        let sdf = self.surface_sdf(sdf);
+        let mut mesh = IsoMesh::default();
+
+        // Not all cells have vertices on the mesh, so we map cell IDs to the
+        // mesh vertex IDs of only the visited leaves.
+        let mut cell_vertex_ids = vec![NULL_MESH_VERTEX_ID; self.all_cells.len()];
+        let mut polygons = ContourPolygons::default();
+        self.dual_contour(
+            |cell_id, cell| {
+                // Leaves that only a shell crosses have no vertex on this
+                // surface.
+                if !cell_is_bipolar(&cell.samples) {
+                    return;
+                }
+                let p = Vec3A::from(cell.vertex_estimate);
+                let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+                cell_vertex_ids[cell_id as usize] = mesh.push_vertex(p, n, cell_id);
+            },
+            |q| polygons.quads.push(q),
+            |tri| polygons.triangles.push(tri),
+        );
+        add_missing_vertices(&polygons, &mut cell_vertex_ids, |cell_id| {
+            let p = Vec3A::from(self.all_cells[cell_id as usize].vertex_estimate);
+            let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+            mesh.push_vertex(p, n, cell_id)
+        });
+        let iso_value = self.iso_value;
+        mesh.add_polygons(polygons, &cell_vertex_ids, options, |p| sdf(p) - iso_value);
+
+        mesh
+    }
+}
+
 /// Repair normals for vertices on sharp edges.
 ///
 /// This may add vertices to the mesh in order to allow multiple normals at the
@@ -13,23 +310,190 @@ pub fn repair_sharp_normals(
     positions: &mut Vec<Vec3A>,
     normals: &mut Vec<Vec3A>,
 ) {
-    for t in tri_indices.chunks_exact_mut(3) {
-        let mut tri = [t[0], t[1], t[2]];
-        let n = tri.map(|v| normals[v as usize]);
-        let p = tri.map(|v| positions[v as usize]);
+    repair_sharp_normals_with(
+        normal_similarity_threshold,
+        tri_indices.chunks_exact_mut(3),
+        positions,
+        normals,
+        |_, _| {},
+    )
+}
 
-        let tri_normal = (p[1] - p[0]).cross(p[2] - p[0]).normalize();
+/// Calls `split_vertex` with the original vertex and the new normal every
+/// time a new vertex is added.
+fn repair_sharp_normals_with<'a>(
+    normal_similarity_threshold: f32,
+    polygons: impl Iterator<Item = &'a mut [MeshVertexId]>,
+    positions: &mut Vec<Vec3A>,
+    normals: &mut Vec<Vec3A>,
+    mut split_vertex: impl FnMut(MeshVertexId, Vec3A),
+) {
+    let mut p = [Vec3A::ZERO; 4];
+    for polygon in polygons {
+        let n = polygon.len();
+        for (p, &v) in p.iter_mut().zip(polygon.iter()) {
+            *p = positions[v as usize];
+        }
+        let polygon_normal = if n == 3 {
+            (p[1] - p[0]).cross(p[2] - p[0])
+        } else {
+            polygon_normal(&p[..n])
+        }
+        .normalize();
 
-        // Force dissident normals to use the triangle's normal.
-        for ti in 0..3 {
-            if n[ti].dot(tri_normal) < normal_similarity_threshold {
+        // Force dissident normals to use the polygon's normal.
+        for (v, &p) in polygon.iter_mut().zip(&p) {
+            if normals[*v as usize].dot(polygon_normal) < normal_similarity_threshold {
                 let new_vert = positions.len() as MeshVertexId;
-                positions.push(p[ti]);
-                normals.push(tri_normal);
-                tri[ti] = new_vert;
+                positions.push(p);
+                normals.push(polygon_normal);
+                split_vertex(*v, polygon_normal);
+                *v = new_vert;
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives::sphere;
+    use ilattice::extent::Extent;
+
+    /// A quad folded along its `v0-v2` diagonal into a ridge, with the vertex
+    /// normals of the ridge.
+    fn ridge_quad() -> IsoMesh {
+        let n = |x, y| Vec3A::new(x, y, 1.0).normalize();
+        IsoMesh {
+            positions: vec![
+                Vec3A::new(0.0, 0.0, 1.0),
+                Vec3A::new(1.0, 0.0, 0.0),
+                Vec3A::new(1.0, 1.0, 1.0),
+                Vec3A::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![Vec3A::Z, n(1.0, -1.0), Vec3A::Z, n(-1.0, 1.0)],
+            cell_ids: vec![0, 1, 2, 3],
+            ..Default::default()
+        }
+    }
 
-        t.copy_from_slice(&tri);
+    fn triangulate(mut mesh: IsoMesh, triangulation: Triangulation) -> IsoMesh {
+        mesh.triangulate_quad([0, 1, 2, 3], triangulation, |p| p.z - 0.5, 0.001);
+        mesh
+    }
+
+    #[test]
+    fn fixed_diagonal_splits_13() {
+        let mesh = triangulate(ridge_quad(), Triangulation::FixedDiagonal);
+        assert_eq!(mesh.tri_indices, [0, 1, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn shortest_diagonal() {
+        let mut mesh = ridge_quad();
+        mesh.positions[2].x = 0.5;
+        let mesh = triangulate(mesh, Triangulation::ShortestDiagonal);
+        assert_eq!(mesh.tri_indices, [0, 1, 2, 0, 2, 3]);
+
+        let mut mesh = ridge_quad();
+        mesh.positions[3].x = 0.8;
+        let mesh = triangulate(mesh, Triangulation::ShortestDiagonal);
+        assert_eq!(mesh.tri_indices, [0, 1, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn normal_consistency_follows_ridge() {
+        let mesh = triangulate(ridge_quad(), Triangulation::NormalConsistency);
+        assert_eq!(mesh.tri_indices, [0, 1, 2, 0, 2, 3]);
+
+        // Normals that agree with the other diagonal.
+        let mut valley = ridge_quad();
+        let n = |x, y| Vec3A::new(x, y, 1.0).normalize();
+        valley.normals = vec![n(1.0, 1.0), Vec3A::Z, n(-1.0, -1.0), Vec3A::Z];
+        let mesh = triangulate(valley, Triangulation::NormalConsistency);
+        assert_eq!(mesh.tri_indices, [0, 1, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn center_vertex_is_projected() {
+        let mesh = triangulate(ridge_quad(), Triangulation::CenterVertex);
+        assert_eq!(mesh.tri_indices, [0, 1, 4, 1, 2, 4, 2, 3, 4, 3, 0, 4]);
+        assert_eq!(mesh.cell_ids[4], CellId::MAX);
+        assert!((mesh.positions[4] - Vec3A::new(0.5, 0.5, 0.5)).length() < 1e-4);
+        assert!((mesh.normals[4] - Vec3A::Z).length() < 1e-3);
+    }
+
+    #[test]
+    fn sharp_normals_are_repaired_on_quads() {
+        let sdf = |p| crate::sdf_primitives::cube(Vec3A::splat(0.5), p);
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let mut octree = CellOctree::build(root, 4, 0.0001, 0.1, sdf).unwrap();
+        // The smallest similarity between a quad's normal and its corners'.
+        let min_similarity = |mesh: &IsoMesh| {
+            let mut min = f32::INFINITY;
+            for quad in mesh.quad_indices.chunks_exact(4) {
+                let p: Vec<_> = quad.iter().map(|&v| mesh.positions[v as usize]).collect();
+                let normal = polygon_normal(&p).normalize();
+                for &v in quad {
+                    min = min.min(mesh.normals[v as usize].dot(normal));
+                }
+            }
+            min
+        };
+
+        let smooth = octree.contour_to_mesh(
+            &MeshOptions {
+                sharp_normal_threshold: None,
+                keep_quads: true,
+                ..Default::default()
+            },
+            sdf,
+        );
+        assert!(min_similarity(&smooth) < 0.9);
+
+        let options = MeshOptions {
+            keep_quads: true,
+            ..Default::default()
+        };
+        let threshold = options.sharp_normal_threshold.unwrap();
+        let mesh = octree.contour_to_mesh(&options, sdf);
+        assert!(!mesh.quad_indices.is_empty());
+        assert!(min_similarity(&mesh) >= threshold);
+        assert!(mesh.positions.len() > smooth.positions.len());
+        assert_eq!(mesh.cell_ids.len(), mesh.positions.len());
+    }
+
+    #[test]
+    fn triangle_counts() {
+        let sdf = |p| sphere(0.6, p);
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let mut octree = CellOctree::build(root, 4, 0.0001, 0.1, sdf).unwrap();
+        let contour = |octree: &mut CellOctree, keep_quads, triangulation| {
+            let options = MeshOptions {
+                sharp_normal_threshold: None,
+                keep_quads,
+                triangulation,
+                ..Default::default()
+            };
+            octree.contour_to_mesh(&options, sdf)
+        };
+
+        let quads = contour(&mut octree, true, Triangulation::FixedDiagonal);
+        let num_quads = quads.quad_indices.len() / 4;
+        let num_tris = quads.tri_indices.len() / 3;
+        assert!(num_quads > 0);
+        for triangulation in [
+            Triangulation::FixedDiagonal,
+            Triangulation::ShortestDiagonal,
+            Triangulation::NormalConsistency,
+        ] {
+            let mesh = contour(&mut octree, false, triangulation);
+            assert!(mesh.quad_indices.is_empty());
+            assert_eq!(mesh.tri_indices.len() / 3, num_tris + 2 * num_quads);
+            assert_eq!(mesh.positions.len(), quads.positions.len());
+        }
+        let mesh = contour(&mut octree, false, Triangulation::CenterVertex);
+        assert_eq!(mesh.tri_indices.len() / 3, num_tris + 4 * num_quads);
+        assert_eq!(mesh.positions.len(), quads.positions.len() + num_quads);
     }
 }
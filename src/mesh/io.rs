@@ -0,0 +1,62 @@
+//! Readers and writers for common mesh file formats.
+//!
+//! Readers only support the subset of each format that the matching writer
+//! produces. Vertices read from files don't belong to any cell, so their
+//! [`IsoMesh::cell_ids`] are `CellId::MAX`.
+
+mod gltf;
+mod obj;
+mod ply;
+mod stl;
+
+pub use gltf::*;
+pub use obj::*;
+pub use ply::*;
+pub use stl::*;
+
+use super::IsoMesh;
+use std::io;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Iterate over all triangles of `mesh`, splitting quads along the same
+/// diagonal as [`Triangulation::FixedDiagonal`](crate::Triangulation).
+fn triangles(mesh: &IsoMesh) -> impl Iterator<Item = [u32; 3]> + '_ {
+    index_triangles(&mesh.tri_indices, &mesh.quad_indices)
+}
+
+/// Like [`triangles`], for a slice of each index buffer.
+fn index_triangles<'a>(
+    tri_indices: &'a [u32],
+    quad_indices: &'a [u32],
+) -> impl Iterator<Item = [u32; 3]> + 'a {
+    let tris = tri_indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]);
+    let quad_tris = quad_indices
+        .chunks_exact(4)
+        .flat_map(|q| [[q[0], q[1], q[3]], [q[1], q[2], q[3]]]);
+    tris.chain(quad_tris)
+}
+
+#[cfg(test)]
+fn test_mesh() -> IsoMesh {
+    use glam::Vec3A;
+
+    let positions = vec![
+        Vec3A::new(0.0, 0.0, 0.0),
+        Vec3A::new(1.0, 0.0, 0.0),
+        Vec3A::new(1.0, 1.0, 0.0),
+        Vec3A::new(0.0, 1.0, 0.0),
+        Vec3A::new(0.5, 0.5, 1.0),
+    ];
+    let normals = vec![Vec3A::Z, Vec3A::Z, Vec3A::Y, Vec3A::X, Vec3A::NEG_Z];
+    IsoMesh {
+        cell_ids: vec![crate::CellId::MAX; positions.len()],
+        positions,
+        normals,
+        tri_indices: vec![0, 1, 4, 1, 2, 4],
+        quad_indices: vec![0, 3, 2, 1],
+        ..Default::default()
+    }
+}
@@ -0,0 +1,645 @@
+use super::{index_triangles, triangles};
+use crate::{IsoMesh, MaterialId, SubmeshedMesh};
+use glam::{Vec2, Vec3A, Vec4};
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A; // "JSON"
+const CHUNK_BIN: u32 = 0x004E_4942; // "BIN\0"
+
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// A metallic-roughness PBR material.
+#[derive(Clone, Debug)]
+pub struct GltfMaterial<'a> {
+    pub name: &'a str,
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for GltfMaterial<'_> {
+    fn default() -> Self {
+        Self {
+            name: "",
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+/// A set of triangles drawn with one material.
+#[derive(Clone, Debug)]
+pub struct GltfPrimitive<'a> {
+    pub tri_indices: Cow<'a, [u32]>,
+    /// Index into the materials given to [`write_glb`].
+    pub material: Option<usize>,
+}
+
+/// Vertex attributes shared by one or more primitives.
+#[derive(Clone, Debug, Default)]
+pub struct GltfMesh<'a> {
+    pub positions: &'a [Vec3A],
+    pub normals: Option<&'a [Vec3A]>,
+    pub uvs: Option<&'a [Vec2]>,
+    /// XYZ is the tangent direction and W is the handedness of the bitangent.
+    pub tangents: Option<&'a [Vec4]>,
+    /// Linear RGBA.
+    pub colors: Option<&'a [Vec4]>,
+    pub primitives: Vec<GltfPrimitive<'a>>,
+}
+
+impl<'a> GltfMesh<'a> {
+    /// Positions, normals, UVs, tangents, and a single primitive with all
+    /// triangles of `mesh`. Quads are triangulated.
+    pub fn from_iso_mesh(mesh: &'a IsoMesh, material: Option<usize>) -> Self {
+        let tri_indices = if mesh.quad_indices.is_empty() {
+            Cow::Borrowed(mesh.tri_indices.as_slice())
+        } else {
+            Cow::Owned(triangles(mesh).flatten().collect())
+        };
+        Self {
+            primitives: vec![GltfPrimitive {
+                tri_indices,
+                material,
+            }],
+            ..Self::vertices(mesh)
+        }
+    }
+
+    /// Vertex attributes like [`Self::from_iso_mesh`], and one primitive per
+    /// submesh of `mesh`, using the glTF material that `material` maps its
+    /// [`MaterialId`] to. Quads are triangulated.
+    pub fn from_submeshes(
+        mesh: &'a SubmeshedMesh,
+        material: impl Fn(MaterialId) -> Option<usize>,
+    ) -> Self {
+        let primitives = mesh
+            .submeshes
+            .iter()
+            .map(|submesh| {
+                let tris = mesh.tri_indices(submesh);
+                let quads = mesh.quad_indices(submesh);
+                let tri_indices = if quads.is_empty() {
+                    Cow::Borrowed(tris)
+                } else {
+                    Cow::Owned(index_triangles(tris, quads).flatten().collect())
+                };
+                GltfPrimitive {
+                    tri_indices,
+                    material: material(submesh.material),
+                }
+            })
+            .collect();
+        Self {
+            primitives,
+            ..Self::vertices(&mesh.mesh)
+        }
+    }
+
+    /// The non-empty vertex attributes of `mesh`, without primitives.
+    fn vertices(mesh: &'a IsoMesh) -> Self {
+        Self {
+            positions: &mesh.positions,
+            normals: (!mesh.normals.is_empty()).then_some(mesh.normals.as_slice()),
+            uvs: (!mesh.uvs.is_empty()).then_some(mesh.uvs.as_slice()),
+            tangents: (!mesh.tangents.is_empty()).then_some(mesh.tangents.as_slice()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Write a binary glTF 2.0 (`.glb`) file containing one node per mesh in
+/// `lods`.
+///
+/// `lods[0]` is the full detail mesh, and any further meshes are
+/// progressively coarser levels of detail linked with the `MSFT_lod`
+/// extension.
+///
+/// Primitives without triangles are skipped, since glTF doesn't allow empty
+/// buffers. Fails if any mesh has no triangles left or any position isn't
+/// finite.
+pub fn write_glb(
+    lods: &[GltfMesh],
+    materials: &[GltfMaterial],
+    mut writer: impl Write,
+) -> io::Result<()> {
+    if lods.is_empty() {
+        return Err(invalid_input("at least one mesh is required"));
+    }
+
+    let mut bin = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    for mesh in lods {
+        meshes.push(pack_mesh(
+            mesh,
+            materials.len(),
+            &mut bin,
+            &mut buffer_views,
+            &mut accessors,
+        )?);
+    }
+
+    let mut json = String::new();
+    json.push_str(r#"{"asset":{"version":"2.0","generator":"octree_dual_contour"}"#);
+    if lods.len() > 1 {
+        json.push_str(r#","extensionsUsed":["MSFT_lod"]"#);
+    }
+    json.push_str(r#","scene":0,"scenes":[{"nodes":[0]}],"nodes":["#);
+    for i in 0..lods.len() {
+        if i > 0 {
+            json.push(',');
+        }
+        write!(json, r#"{{"mesh":{i}"#).unwrap();
+        if i == 0 && lods.len() > 1 {
+            let ids: Vec<_> = (1..lods.len()).map(|id| id.to_string()).collect();
+            write!(
+                json,
+                r#","extensions":{{"MSFT_lod":{{"ids":[{}]}}}}"#,
+                ids.join(",")
+            )
+            .unwrap();
+        }
+        json.push('}');
+    }
+    write!(json, r#"],"meshes":[{}]"#, meshes.join(",")).unwrap();
+    if !materials.is_empty() {
+        let materials: Vec<_> = materials.iter().map(material_json).collect();
+        write!(json, r#","materials":[{}]"#, materials.join(",")).unwrap();
+    }
+    write!(
+        json,
+        r#","accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+        accessors.join(","),
+        buffer_views.join(","),
+        bin.len()
+    )
+    .unwrap();
+
+    // Chunks must be 4-byte aligned. JSON is padded with spaces.
+    let mut json = json.into_bytes();
+    json.resize(align4(json.len()), b' ');
+    bin.resize(align4(bin.len()), 0);
+
+    let total_len = 12 + 8 + json.len() + 8 + bin.len();
+    let total_len =
+        u32::try_from(total_len).map_err(|_| invalid_input("mesh is too large for GLB"))?;
+    for word in [GLB_MAGIC, GLB_VERSION, total_len] {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    for (chunk_type, chunk) in [(CHUNK_JSON, &json), (CHUNK_BIN, &bin)] {
+        writer.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        writer.write_all(&chunk_type.to_le_bytes())?;
+        writer.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+/// Appends the mesh's data to `bin` and returns its JSON.
+fn pack_mesh(
+    mesh: &GltfMesh,
+    num_materials: usize,
+    bin: &mut Vec<u8>,
+    buffer_views: &mut Vec<String>,
+    accessors: &mut Vec<String>,
+) -> io::Result<String> {
+    let num_vertices = mesh.positions.len();
+    let check_len = |name: &str, len: Option<usize>| match len {
+        Some(len) if len != num_vertices => Err(invalid_input(format!(
+            "number of {name} doesn't match positions"
+        ))),
+        _ => Ok(()),
+    };
+    check_len("normals", mesh.normals.map(<[_]>::len))?;
+    check_len("UVs", mesh.uvs.map(<[_]>::len))?;
+    check_len("tangents", mesh.tangents.map(<[_]>::len))?;
+    check_len("colors", mesh.colors.map(<[_]>::len))?;
+    // The position bounds are written to the JSON, which can't represent
+    // them.
+    if !mesh.positions.iter().all(|p| p.is_finite()) {
+        return Err(invalid_input("positions must be finite"));
+    }
+    // glTF forbids empty buffer views and meshes without primitives.
+    let primitives: Vec<_> = mesh
+        .primitives
+        .iter()
+        .filter(|p| !p.tri_indices.is_empty())
+        .collect();
+    if primitives.is_empty() {
+        return Err(invalid_input("mesh has no triangles"));
+    }
+
+    let mut push_accessor = |floats: &[f32],
+                             components: usize,
+                             accessor_type: &str,
+                             target: u32,
+                             bounds: Option<([f32; 3], [f32; 3])>| {
+        let view = push_buffer_view(
+            bin,
+            buffer_views,
+            floats.iter().map(|x| x.to_le_bytes()),
+            target,
+        );
+        let mut accessor = format!(
+            r#"{{"bufferView":{view},"componentType":{COMPONENT_FLOAT},"count":{},"type":"{accessor_type}""#,
+            floats.len() / components
+        );
+        if let Some((min, max)) = bounds {
+            write!(
+                accessor,
+                r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                min[0], min[1], min[2], max[0], max[1], max[2]
+            )
+            .unwrap();
+        }
+        accessor.push('}');
+        accessors.push(accessor);
+        accessors.len() - 1
+    };
+
+    // glTF requires bounds for positions.
+    let (min, max) = mesh.positions.iter().fold(
+        (Vec3A::splat(f32::INFINITY), Vec3A::splat(f32::NEG_INFINITY)),
+        |(min, max), &p| (min.min(p), max.max(p)),
+    );
+    let bounds = Some((min.to_array(), max.to_array()));
+    let vec3_floats = |v: &[Vec3A]| -> Vec<f32> { v.iter().flat_map(|p| p.to_array()).collect() };
+
+    let mut attributes = vec![format!(
+        r#""POSITION":{}"#,
+        push_accessor(
+            &vec3_floats(mesh.positions),
+            3,
+            "VEC3",
+            TARGET_ARRAY_BUFFER,
+            bounds
+        )
+    )];
+    if let Some(normals) = mesh.normals {
+        let id = push_accessor(&vec3_floats(normals), 3, "VEC3", TARGET_ARRAY_BUFFER, None);
+        attributes.push(format!(r#""NORMAL":{id}"#));
+    }
+    if let Some(uvs) = mesh.uvs {
+        let floats: Vec<_> = uvs.iter().flat_map(|uv| uv.to_array()).collect();
+        let id = push_accessor(&floats, 2, "VEC2", TARGET_ARRAY_BUFFER, None);
+        attributes.push(format!(r#""TEXCOORD_0":{id}"#));
+    }
+    if let Some(tangents) = mesh.tangents {
+        let floats: Vec<_> = tangents.iter().flat_map(|t| t.to_array()).collect();
+        let id = push_accessor(&floats, 4, "VEC4", TARGET_ARRAY_BUFFER, None);
+        attributes.push(format!(r#""TANGENT":{id}"#));
+    }
+    if let Some(colors) = mesh.colors {
+        let floats: Vec<_> = colors.iter().flat_map(|c| c.to_array()).collect();
+        let id = push_accessor(&floats, 4, "VEC4", TARGET_ARRAY_BUFFER, None);
+        attributes.push(format!(r#""COLOR_0":{id}"#));
+    }
+    let attributes = attributes.join(",");
+
+    let mut primitive_jsons = Vec::new();
+    for primitive in primitives {
+        if primitive
+            .tri_indices
+            .iter()
+            .any(|&v| v as usize >= num_vertices)
+        {
+            return Err(invalid_input("triangle index out of bounds"));
+        }
+        let view = push_buffer_view(
+            bin,
+            buffer_views,
+            primitive.tri_indices.iter().map(|v| v.to_le_bytes()),
+            TARGET_ELEMENT_ARRAY_BUFFER,
+        );
+        accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":{COMPONENT_UNSIGNED_INT},"count":{},"type":"SCALAR"}}"#,
+            primitive.tri_indices.len()
+        ));
+        let mut json = format!(
+            r#"{{"attributes":{{{attributes}}},"indices":{}"#,
+            accessors.len() - 1
+        );
+        if let Some(material) = primitive.material {
+            if material >= num_materials {
+                return Err(invalid_input("material index out of bounds"));
+            }
+            write!(json, r#","material":{material}"#).unwrap();
+        }
+        json.push('}');
+        primitive_jsons.push(json);
+    }
+
+    Ok(format!(
+        r#"{{"primitives":[{}]}}"#,
+        primitive_jsons.join(",")
+    ))
+}
+
+/// Appends 4-byte words to `bin` and returns the ID of their buffer view.
+fn push_buffer_view(
+    bin: &mut Vec<u8>,
+    buffer_views: &mut Vec<String>,
+    words: impl Iterator<Item = [u8; 4]>,
+    target: u32,
+) -> usize {
+    let offset = bin.len();
+    bin.extend(words.flatten());
+    buffer_views.push(format!(
+        r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{},"target":{target}}}"#,
+        bin.len() - offset
+    ));
+    buffer_views.len() - 1
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn material_json(material: &GltfMaterial) -> String {
+    let [r, g, b, a] = material.base_color;
+    format!(
+        r#"{{"name":{},"pbrMetallicRoughness":{{"baseColorFactor":[{r},{g},{b},{a}],"metallicFactor":{},"roughnessFactor":{}}}}}"#,
+        json_string(material.name),
+        material.metallic,
+        material.roughness
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn invalid_input(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::io::test_mesh;
+    use serde_json::Value;
+
+    /// Splits a GLB file into its JSON and binary chunks.
+    fn parse_glb(bytes: &[u8]) -> (Value, &[u8]) {
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        assert_eq!(word(0), GLB_MAGIC);
+        assert_eq!(word(4), GLB_VERSION);
+        assert_eq!(word(8) as usize, bytes.len());
+
+        let json_len = word(12) as usize;
+        assert_eq!(word(16), CHUNK_JSON);
+        assert_eq!(json_len % 4, 0);
+        let json = serde_json::from_slice(&bytes[20..20 + json_len]).unwrap();
+
+        let bin_start = 20 + json_len;
+        let bin_len = word(bin_start) as usize;
+        assert_eq!(word(bin_start + 4), CHUNK_BIN);
+        assert_eq!(bin_len % 4, 0);
+        assert_eq!(bin_start + 8 + bin_len, bytes.len());
+        (json, &bytes[bin_start + 8..])
+    }
+
+    /// Reads the floats or integers of an accessor as `f64`.
+    fn read_accessor(json: &Value, bin: &[u8], accessor: u64) -> Vec<f64> {
+        let accessor = &json["accessors"][accessor as usize];
+        let view = &json["bufferViews"][accessor["bufferView"].as_u64().unwrap() as usize];
+        let offset = view["byteOffset"].as_u64().unwrap() as usize;
+        let len = view["byteLength"].as_u64().unwrap() as usize;
+        assert_eq!(offset % 4, 0);
+        assert!(offset + len <= json["buffers"][0]["byteLength"].as_u64().unwrap() as usize);
+
+        let components = match accessor["type"].as_str().unwrap() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            t => panic!("unexpected accessor type {t}"),
+        };
+        let count = accessor["count"].as_u64().unwrap() as usize;
+        assert_eq!(len, 4 * components * count);
+
+        bin[offset..offset + len]
+            .chunks_exact(4)
+            .map(|w| {
+                let w = w.try_into().unwrap();
+                match accessor["componentType"].as_u64().unwrap() as u32 {
+                    COMPONENT_FLOAT => f32::from_le_bytes(w) as f64,
+                    COMPONENT_UNSIGNED_INT => u32::from_le_bytes(w) as f64,
+                    c => panic!("unexpected component type {c}"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_mesh_structure_and_offsets() {
+        let mesh = test_mesh();
+        let uvs: Vec<_> = mesh.positions.iter().map(|p| Vec2::new(p.x, p.y)).collect();
+        let tangents = vec![Vec4::new(1.0, 0.0, 0.0, 1.0); mesh.positions.len()];
+        let colors = vec![Vec4::ONE; mesh.positions.len()];
+        let mut gltf_mesh = GltfMesh::from_iso_mesh(&mesh, Some(0));
+        gltf_mesh.uvs = Some(&uvs);
+        gltf_mesh.tangents = Some(&tangents);
+        gltf_mesh.colors = Some(&colors);
+        gltf_mesh.primitives.push(GltfPrimitive {
+            tri_indices: Cow::Owned(vec![0, 1, 2]),
+            material: Some(1),
+        });
+        let materials = [
+            GltfMaterial {
+                name: "red \"stone\"",
+                base_color: [1.0, 0.0, 0.0, 1.0],
+                ..Default::default()
+            },
+            GltfMaterial::default(),
+        ];
+
+        let mut bytes = Vec::new();
+        write_glb(&[gltf_mesh], &materials, &mut bytes).unwrap();
+        let (json, bin) = parse_glb(&bytes);
+
+        assert_eq!(json["asset"]["version"], "2.0");
+        assert_eq!(json["scenes"][0]["nodes"], serde_json::json!([0]));
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 1);
+        assert!(json.get("extensionsUsed").is_none());
+        assert_eq!(json["materials"][0]["name"], "red \"stone\"");
+        assert_eq!(
+            json["buffers"][0]["byteLength"].as_u64().unwrap() as usize,
+            bin.len()
+        );
+
+        let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 2);
+        assert_eq!(primitives[0]["material"], 0);
+        assert_eq!(primitives[1]["material"], 1);
+
+        let attributes = &primitives[0]["attributes"];
+        let attribute = |name: &str| read_accessor(&json, bin, attributes[name].as_u64().unwrap());
+        let positions = attribute("POSITION");
+        let expected: Vec<_> = mesh
+            .positions
+            .iter()
+            .flat_map(|p| p.to_array())
+            .map(f64::from)
+            .collect();
+        assert_eq!(positions, expected);
+        let position_accessor =
+            &json["accessors"][attributes["POSITION"].as_u64().unwrap() as usize];
+        let bound = |name: &str| -> Vec<f64> {
+            let bound = position_accessor[name].as_array().unwrap();
+            bound.iter().map(|x| x.as_f64().unwrap()).collect()
+        };
+        assert_eq!(bound("min"), [0.0, 0.0, 0.0]);
+        assert_eq!(bound("max"), [1.0, 1.0, 1.0]);
+        assert_eq!(attribute("NORMAL").len(), 3 * mesh.positions.len());
+        assert_eq!(attribute("TEXCOORD_0").len(), 2 * mesh.positions.len());
+        assert_eq!(attribute("TANGENT").len(), 4 * mesh.positions.len());
+        assert_eq!(attribute("COLOR_0").len(), 4 * mesh.positions.len());
+
+        let indices = read_accessor(&json, bin, primitives[0]["indices"].as_u64().unwrap());
+        let expected: Vec<_> = triangles(&mesh).flatten().map(f64::from).collect();
+        assert_eq!(indices, expected);
+        let indices = read_accessor(&json, bin, primitives[1]["indices"].as_u64().unwrap());
+        assert_eq!(indices, [0.0, 1.0, 2.0]);
+
+        // Buffer views don't overlap.
+        let mut views: Vec<_> = json["bufferViews"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                let offset = v["byteOffset"].as_u64().unwrap();
+                (offset, offset + v["byteLength"].as_u64().unwrap())
+            })
+            .collect();
+        views.sort();
+        assert!(views.windows(2).all(|w| w[0].1 <= w[1].0));
+    }
+
+    #[test]
+    fn submeshes_become_primitives() {
+        let mesh = SubmeshedMesh {
+            mesh: test_mesh(),
+            submeshes: vec![
+                crate::Submesh {
+                    material: 2,
+                    tri_indices: 0..3,
+                    quad_indices: 0..0,
+                },
+                crate::Submesh {
+                    material: 5,
+                    tri_indices: 3..6,
+                    quad_indices: 0..4,
+                },
+            ],
+        };
+        let gltf_mesh = GltfMesh::from_submeshes(&mesh, |m| (m == 2).then_some(0));
+        let mut bytes = Vec::new();
+        write_glb(&[gltf_mesh], &[GltfMaterial::default()], &mut bytes).unwrap();
+        let (json, bin) = parse_glb(&bytes);
+
+        let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 2);
+        assert_eq!(primitives[0]["material"], 0);
+        assert!(primitives[1].get("material").is_none());
+        let indices =
+            |i: usize| read_accessor(&json, bin, primitives[i]["indices"].as_u64().unwrap());
+        assert_eq!(indices(0), [0.0, 1.0, 4.0]);
+        assert_eq!(indices(1), [1.0, 2.0, 4.0, 0.0, 3.0, 1.0, 3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn lods_use_msft_lod() {
+        let mesh = test_mesh();
+        let lods = [
+            GltfMesh::from_iso_mesh(&mesh, None),
+            GltfMesh::from_iso_mesh(&mesh, None),
+            GltfMesh::from_iso_mesh(&mesh, None),
+        ];
+        let mut bytes = Vec::new();
+        write_glb(&lods, &[], &mut bytes).unwrap();
+        let (json, _bin) = parse_glb(&bytes);
+
+        assert_eq!(json["extensionsUsed"], serde_json::json!(["MSFT_lod"]));
+        assert_eq!(json["scenes"][0]["nodes"], serde_json::json!([0]));
+        let nodes = json["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(
+            nodes[0]["extensions"]["MSFT_lod"]["ids"],
+            serde_json::json!([1, 2])
+        );
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(node["mesh"], i);
+        }
+        assert_eq!(json["meshes"].as_array().unwrap().len(), 3);
+        assert!(json.get("materials").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let mesh = test_mesh();
+        let mut gltf_mesh = GltfMesh::from_iso_mesh(&mesh, Some(0));
+        assert!(write_glb(&[gltf_mesh.clone()], &[], Vec::new()).is_err());
+
+        gltf_mesh.primitives[0].material = None;
+        gltf_mesh.normals = Some(&mesh.normals[..1]);
+        assert!(write_glb(&[gltf_mesh], &[], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_non_finite_positions() {
+        for x in [f32::NAN, f32::INFINITY] {
+            let mut mesh = test_mesh();
+            mesh.positions[2].x = x;
+            let gltf_mesh = GltfMesh::from_iso_mesh(&mesh, None);
+            assert!(write_glb(&[gltf_mesh], &[], Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn skips_empty_primitives() {
+        let mesh = test_mesh();
+        let mut gltf_mesh = GltfMesh::from_iso_mesh(&mesh, None);
+        gltf_mesh.primitives.insert(
+            0,
+            GltfPrimitive {
+                tri_indices: Cow::Borrowed(&[]),
+                material: None,
+            },
+        );
+        let mut bytes = Vec::new();
+        write_glb(&[gltf_mesh], &[], &mut bytes).unwrap();
+        let (json, _bin) = parse_glb(&bytes);
+        assert_eq!(json["meshes"][0]["primitives"].as_array().unwrap().len(), 1);
+        for view in json["bufferViews"].as_array().unwrap() {
+            assert!(view["byteLength"].as_u64().unwrap() > 0);
+        }
+
+        // Nothing is left of an empty mesh.
+        let empty = IsoMesh::default();
+        let gltf_mesh = GltfMesh::from_iso_mesh(&empty, None);
+        assert!(write_glb(&[gltf_mesh], &[], Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use super::invalid_data;
+use crate::{CellId, IsoMesh, MeshVertexId};
+use glam::Vec3A;
+use std::io::{self, BufRead, Write};
+
+/// Write `mesh` as a Wavefront OBJ file with positions and normals.
+///
+/// Quads are written as 4-sided faces.
+pub fn write_obj(mesh: &IsoMesh, mut writer: impl Write) -> io::Result<()> {
+    for p in &mesh.positions {
+        writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for n in &mesh.normals {
+        writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    let has_normals = !mesh.normals.is_empty();
+    let faces = mesh
+        .tri_indices
+        .chunks_exact(3)
+        .chain(mesh.quad_indices.chunks_exact(4));
+    for face in faces {
+        write!(writer, "f")?;
+        for &v in face {
+            // OBJ indices are 1-based.
+            let v = v + 1;
+            if has_normals {
+                write!(writer, " {v}//{v}")?;
+            } else {
+                write!(writer, " {v}")?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Read an OBJ file written by [`write_obj`].
+///
+/// Each face vertex must use the same index for its position and normal.
+pub fn read_obj(reader: impl BufRead) -> io::Result<IsoMesh> {
+    let mut mesh = IsoMesh::default();
+    for line in reader.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => mesh.positions.push(parse_vec3(tokens)?),
+            Some("vn") => mesh.normals.push(parse_vec3(tokens)?),
+            Some("f") => {
+                let face = tokens
+                    .map(parse_face_vertex)
+                    .collect::<io::Result<Vec<_>>>()?;
+                match face.len() {
+                    3 => mesh.tri_indices.extend_from_slice(&face),
+                    4 => mesh.quad_indices.extend_from_slice(&face),
+                    n => return Err(invalid_data(format!("unsupported face with {n} vertices"))),
+                }
+            }
+            // Ignore comments and unsupported statements.
+            _ => {}
+        }
+    }
+    if !mesh.normals.is_empty() && mesh.normals.len() != mesh.positions.len() {
+        return Err(invalid_data("number of normals doesn't match positions"));
+    }
+    let num_vertices = mesh.positions.len();
+    if mesh
+        .tri_indices
+        .iter()
+        .chain(&mesh.quad_indices)
+        .any(|&v| v as usize >= num_vertices)
+    {
+        return Err(invalid_data("face index out of bounds"));
+    }
+    mesh.cell_ids = vec![CellId::MAX; num_vertices];
+    Ok(mesh)
+}
+
+fn parse_vec3<'a>(mut tokens: impl Iterator<Item = &'a str>) -> io::Result<Vec3A> {
+    let mut coords = [0.0; 3];
+    for c in &mut coords {
+        *c = tokens
+            .next()
+            .ok_or_else(|| invalid_data("missing vector coordinate"))?
+            .parse()
+            .map_err(|_| invalid_data("invalid vector coordinate"))?;
+    }
+    Ok(coords.into())
+}
+
+fn parse_face_vertex(token: &str) -> io::Result<MeshVertexId> {
+    let mut indices = token.split('/');
+    let position: MeshVertexId = indices
+        .next()
+        .and_then(|i| i.parse().ok())
+        .ok_or_else(|| invalid_data(format!("invalid face vertex {token:?}")))?;
+    if let Some(normal) = indices.nth(1) {
+        if normal.parse() != Ok(position) {
+            return Err(invalid_data(
+                "face vertex position and normal indices differ",
+            ));
+        }
+    }
+    position
+        .checked_sub(1)
+        .ok_or_else(|| invalid_data("OBJ indices start at 1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::io::test_mesh;
+
+    #[test]
+    fn round_trip() {
+        let mesh = test_mesh();
+        let mut bytes = Vec::new();
+        write_obj(&mesh, &mut bytes).unwrap();
+        let read = read_obj(bytes.as_slice()).unwrap();
+
+        assert_eq!(read.positions, mesh.positions);
+        assert_eq!(read.normals, mesh.normals);
+        assert_eq!(read.tri_indices, mesh.tri_indices);
+        assert_eq!(read.quad_indices, mesh.quad_indices);
+        assert_eq!(read.cell_ids, mesh.cell_ids);
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_face() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+        assert!(read_obj(obj.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use super::invalid_data;
+use crate::{CellId, IsoMesh, MeshVertexId};
+use glam::Vec3A;
+use std::io::{self, BufRead, Write};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+/// A custom per-vertex `float` property, like the QEF error of each vertex's
+/// cell.
+#[derive(Clone, Copy, Debug)]
+pub struct PlyAttribute<'a> {
+    pub name: &'a str,
+    /// One value per vertex.
+    pub values: &'a [f32],
+}
+
+/// A custom per-vertex property read from a PLY file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlyAttributeBuf {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+/// The vertex properties for positions and normals.
+const FIXED_PROPERTIES: [&str; 6] = ["x", "y", "z", "nx", "ny", "nz"];
+
+/// Write `mesh` as a PLY file with positions, normals, and `attributes`.
+///
+/// Quads are written as 4-sided faces. Fails if an attribute name is empty,
+/// contains whitespace, is used twice, or is one of the position and normal
+/// properties, since the file couldn't be read back.
+pub fn write_ply(
+    mesh: &IsoMesh,
+    format: PlyFormat,
+    attributes: &[PlyAttribute],
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let num_vertices = mesh.positions.len();
+    let has_normals = !mesh.normals.is_empty();
+    for (i, attr) in attributes.iter().enumerate() {
+        if attr.values.len() != num_vertices {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "attribute {:?} doesn't have one value per vertex",
+                    attr.name
+                ),
+            ));
+        }
+        let name = attr.name;
+        if name.is_empty()
+            || name.contains(char::is_whitespace)
+            || FIXED_PROPERTIES.contains(&name)
+            || attributes[..i].iter().any(|a| a.name == name)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid attribute name {name:?}"),
+            ));
+        }
+    }
+
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+    };
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {format_name} 1.0")?;
+    writeln!(writer, "element vertex {num_vertices}")?;
+    let mut property_names = FIXED_PROPERTIES[..if has_normals { 6 } else { 3 }].to_vec();
+    property_names.extend(attributes.iter().map(|a| a.name));
+    for name in &property_names {
+        writeln!(writer, "property float {name}")?;
+    }
+    let num_faces = mesh.tri_indices.len() / 3 + mesh.quad_indices.len() / 4;
+    writeln!(writer, "element face {num_faces}")?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    let mut vertex = Vec::with_capacity(property_names.len());
+    for v in 0..num_vertices {
+        vertex.clear();
+        vertex.extend_from_slice(&mesh.positions[v].to_array());
+        if has_normals {
+            vertex.extend_from_slice(&mesh.normals[v].to_array());
+        }
+        vertex.extend(attributes.iter().map(|a| a.values[v]));
+        match format {
+            PlyFormat::Ascii => {
+                let line: Vec<_> = vertex.iter().map(f32::to_string).collect();
+                writeln!(writer, "{}", line.join(" "))?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                for x in &vertex {
+                    writer.write_all(&x.to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    let faces = mesh
+        .tri_indices
+        .chunks_exact(3)
+        .chain(mesh.quad_indices.chunks_exact(4));
+    for face in faces {
+        match format {
+            PlyFormat::Ascii => {
+                let line: Vec<_> = face.iter().map(MeshVertexId::to_string).collect();
+                writeln!(writer, "{} {}", face.len(), line.join(" "))?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                writer.write_all(&[face.len() as u8])?;
+                for v in face {
+                    writer.write_all(&v.to_le_bytes())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a PLY file written by [`write_ply`].
+///
+/// Vertex properties other than positions and normals are returned as
+/// attributes.
+pub fn read_ply(mut reader: impl BufRead) -> io::Result<(IsoMesh, Vec<PlyAttributeBuf>)> {
+    let header = PlyHeader::read(&mut reader)?;
+
+    // The counts in the header can't be trusted for preallocation, so a
+    // corrupt file fails when it runs out of data instead.
+    let mut vertex = vec![0.0; header.vertex_properties.len()];
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    match header.format {
+        PlyFormat::Ascii => {
+            let mut lines = reader.lines();
+            let mut next_line = || {
+                lines
+                    .next()
+                    .unwrap_or_else(|| Err(invalid_data("unexpected end of file")))
+            };
+            for _ in 0..header.num_vertices {
+                let line = next_line()?;
+                let mut tokens = line.split_whitespace();
+                for x in &mut vertex {
+                    *x = parse_token(tokens.next())?;
+                }
+                vertices.push(vertex.clone());
+            }
+            for _ in 0..header.num_faces {
+                let line = next_line()?;
+                let mut tokens = line.split_whitespace();
+                let n: usize = parse_token(tokens.next())?;
+                let face = (0..n)
+                    .map(|_| parse_token(tokens.next()))
+                    .collect::<io::Result<Vec<MeshVertexId>>>()?;
+                faces.push(face);
+            }
+        }
+        PlyFormat::BinaryLittleEndian => {
+            let mut word = [0; 4];
+            for _ in 0..header.num_vertices {
+                for x in &mut vertex {
+                    reader.read_exact(&mut word)?;
+                    *x = f32::from_le_bytes(word);
+                }
+                vertices.push(vertex.clone());
+            }
+            for _ in 0..header.num_faces {
+                let mut n = [0];
+                reader.read_exact(&mut n)?;
+                let face = (0..n[0])
+                    .map(|_| {
+                        reader.read_exact(&mut word)?;
+                        Ok(MeshVertexId::from_le_bytes(word))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                faces.push(face);
+            }
+        }
+    }
+
+    let property_index = |name: &str| header.vertex_properties.iter().position(|p| p == name);
+    let vec3_indices = |names: [&str; 3]| -> Option<[usize; 3]> {
+        let [x, y, z] = names.map(property_index);
+        Some([x?, y?, z?])
+    };
+    let position_indices =
+        vec3_indices(["x", "y", "z"]).ok_or_else(|| invalid_data("missing vertex position"))?;
+    let normal_indices = vec3_indices(["nx", "ny", "nz"]);
+    let get_vec3 = |v: &[f32], [x, y, z]: [usize; 3]| Vec3A::new(v[x], v[y], v[z]);
+
+    let mut mesh = IsoMesh {
+        positions: vertices
+            .iter()
+            .map(|v| get_vec3(v, position_indices))
+            .collect(),
+        cell_ids: vec![CellId::MAX; vertices.len()],
+        ..Default::default()
+    };
+    if let Some(normal_indices) = normal_indices {
+        mesh.normals = vertices
+            .iter()
+            .map(|v| get_vec3(v, normal_indices))
+            .collect();
+    }
+    for face in faces {
+        if face.iter().any(|&v| v as usize >= vertices.len()) {
+            return Err(invalid_data("face index out of bounds"));
+        }
+        match face.len() {
+            3 => mesh.tri_indices.extend_from_slice(&face),
+            4 => mesh.quad_indices.extend_from_slice(&face),
+            n => return Err(invalid_data(format!("unsupported face with {n} vertices"))),
+        }
+    }
+
+    let standard_names = ["x", "y", "z", "nx", "ny", "nz"];
+    let attributes = header
+        .vertex_properties
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !standard_names.contains(&name.as_str()))
+        .map(|(i, name)| PlyAttributeBuf {
+            name: name.clone(),
+            values: vertices.iter().map(|v| v[i]).collect(),
+        })
+        .collect();
+
+    Ok((mesh, attributes))
+}
+
+struct PlyHeader {
+    format: PlyFormat,
+    num_vertices: usize,
+    vertex_properties: Vec<String>,
+    num_faces: usize,
+}
+
+impl PlyHeader {
+    fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut format = None;
+        let mut num_vertices = None;
+        let mut vertex_properties = Vec::new();
+        let mut num_faces = None;
+
+        let mut line = String::new();
+        let mut read_line = |line: &mut String| -> io::Result<()> {
+            line.clear();
+            if reader.read_line(line)? == 0 {
+                return Err(invalid_data("unexpected end of PLY header"));
+            }
+            Ok(())
+        };
+
+        read_line(&mut line)?;
+        if line.trim_end() != "ply" {
+            return Err(invalid_data("missing PLY magic number"));
+        }
+        loop {
+            read_line(&mut line)?;
+            let tokens: Vec<_> = line.split_whitespace().collect();
+            match tokens[..] {
+                ["end_header"] => break,
+                ["comment", ..] | [] => {}
+                ["format", "ascii", "1.0"] => format = Some(PlyFormat::Ascii),
+                ["format", "binary_little_endian", "1.0"] => {
+                    format = Some(PlyFormat::BinaryLittleEndian)
+                }
+                ["element", "vertex", n] if num_faces.is_none() => {
+                    num_vertices = Some(parse_token(Some(n))?)
+                }
+                ["element", "face", n] if num_vertices.is_some() => {
+                    num_faces = Some(parse_token(Some(n))?)
+                }
+                ["property", "float", name] if num_faces.is_none() && num_vertices.is_some() => {
+                    vertex_properties.push(name.to_string())
+                }
+                ["property", "list", "uchar", "uint" | "int", "vertex_indices"]
+                    if num_faces.is_some() => {}
+                _ => {
+                    return Err(invalid_data(format!(
+                        "unsupported PLY header line {:?}",
+                        line.trim_end()
+                    )))
+                }
+            }
+        }
+
+        Ok(Self {
+            format: format.ok_or_else(|| invalid_data("missing PLY format"))?,
+            num_vertices: num_vertices.ok_or_else(|| invalid_data("missing vertex element"))?,
+            vertex_properties,
+            num_faces: num_faces.unwrap_or(0),
+        })
+    }
+}
+
+fn parse_token<T: std::str::FromStr>(token: Option<&str>) -> io::Result<T> {
+    token
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| invalid_data(format!("invalid PLY value {token:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::io::test_mesh;
+
+    fn round_trip(format: PlyFormat) {
+        let mesh = test_mesh();
+        let qef_errors = [0.0, 0.5, 1.0, 1.5, 2.0];
+        let attributes = [PlyAttribute {
+            name: "qef_error",
+            values: &qef_errors,
+        }];
+        let mut bytes = Vec::new();
+        write_ply(&mesh, format, &attributes, &mut bytes).unwrap();
+        let (read, read_attributes) = read_ply(bytes.as_slice()).unwrap();
+
+        assert_eq!(read.positions, mesh.positions);
+        assert_eq!(read.normals, mesh.normals);
+        assert_eq!(read.tri_indices, mesh.tri_indices);
+        assert_eq!(read.quad_indices, mesh.quad_indices);
+        assert_eq!(read.cell_ids, mesh.cell_ids);
+        assert_eq!(
+            read_attributes,
+            [PlyAttributeBuf {
+                name: "qef_error".into(),
+                values: qef_errors.to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        round_trip(PlyFormat::Ascii);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(PlyFormat::BinaryLittleEndian);
+    }
+
+    #[test]
+    fn rejects_mismatched_attribute() {
+        let attributes = [PlyAttribute {
+            name: "qef_error",
+            values: &[0.0],
+        }];
+        let result = write_ply(&test_mesh(), PlyFormat::Ascii, &attributes, Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_unreadable_attribute_names() {
+        let values = [0.0; 5];
+        for names in [
+            [""; 2],
+            ["qef error", "a"],
+            ["a\tb", "c"],
+            ["nx", "a"],
+            ["a", "a"],
+        ] {
+            let attributes = names.map(|name| PlyAttribute {
+                name,
+                values: &values,
+            });
+            let result = write_ply(&test_mesh(), PlyFormat::Ascii, &attributes, Vec::new());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let mut bytes = Vec::new();
+        write_ply(&test_mesh(), PlyFormat::BinaryLittleEndian, &[], &mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(read_ply(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_counts() {
+        for format in ["ascii", "binary_little_endian"] {
+            let ply = format!(
+                "ply\nformat {format} 1.0\nelement vertex {}\nproperty float x\n\
+                 property float y\nproperty float z\nelement face {}\n\
+                 property list uchar uint vertex_indices\nend_header\n",
+                usize::MAX,
+                usize::MAX
+            );
+            assert!(read_ply(ply.as_bytes()).is_err());
+        }
+    }
+}
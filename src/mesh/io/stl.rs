@@ -0,0 +1,103 @@
+use super::{invalid_data, triangles};
+use crate::{CellId, IsoMesh};
+use glam::Vec3A;
+use std::io::{self, Read, Write};
+
+/// Write the triangles of `mesh` as a binary STL file.
+///
+/// Quads are triangulated. Facet normals are computed from the triangle
+/// positions.
+pub fn write_stl(mesh: &IsoMesh, mut writer: impl Write) -> io::Result<()> {
+    let mut header = [0; 80];
+    let title = b"octree_dual_contour";
+    header[..title.len()].copy_from_slice(title);
+    writer.write_all(&header)?;
+
+    let num_triangles = mesh.tri_indices.len() / 3 + 2 * (mesh.quad_indices.len() / 4);
+    writer.write_all(&(num_triangles as u32).to_le_bytes())?;
+
+    for tri in triangles(mesh) {
+        let p = tri.map(|v| mesh.positions[v as usize]);
+        let normal = (p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero();
+        for v in [normal, p[0], p[1], p[2]] {
+            for x in v.to_array() {
+                writer.write_all(&x.to_le_bytes())?;
+            }
+        }
+        // Attribute byte count.
+        writer.write_all(&[0; 2])?;
+    }
+    Ok(())
+}
+
+/// Read a binary STL file.
+///
+/// STL doesn't share vertices between triangles, so each triangle gets 3 new
+/// vertices with the facet normal.
+pub fn read_stl(mut reader: impl Read) -> io::Result<IsoMesh> {
+    let mut header = [0; 80];
+    reader.read_exact(&mut header)?;
+    let mut word = [0; 4];
+    reader.read_exact(&mut word)?;
+    let num_triangles = u32::from_le_bytes(word) as usize;
+
+    let mut mesh = IsoMesh::default();
+    let mut facet = [0; 50];
+    for _ in 0..num_triangles {
+        reader.read_exact(&mut facet).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                invalid_data("STL file has fewer triangles than its header says")
+            } else {
+                e
+            }
+        })?;
+        let float = |i: usize| f32::from_le_bytes(facet[4 * i..4 * i + 4].try_into().unwrap());
+        let vec3 = |v: usize| Vec3A::new(float(3 * v), float(3 * v + 1), float(3 * v + 2));
+        let normal = vec3(0);
+        for v in 1..4 {
+            mesh.tri_indices.push(mesh.positions.len() as u32);
+            mesh.positions.push(vec3(v));
+            mesh.normals.push(normal);
+        }
+    }
+    mesh.cell_ids = vec![CellId::MAX; mesh.positions.len()];
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::io::test_mesh;
+
+    #[test]
+    fn round_trip() {
+        let mesh = test_mesh();
+        let mut bytes = Vec::new();
+        write_stl(&mesh, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 84 + 50 * 4);
+        let read = read_stl(bytes.as_slice()).unwrap();
+
+        let expected_positions: Vec<_> = triangles(&mesh)
+            .flatten()
+            .map(|v| mesh.positions[v as usize])
+            .collect();
+        assert_eq!(read.positions, expected_positions);
+        assert_eq!(read.tri_indices, (0..12).collect::<Vec<u32>>());
+        for (tri, normals) in read
+            .positions
+            .chunks_exact(3)
+            .zip(read.normals.chunks_exact(3))
+        {
+            let expected = (tri[1] - tri[0]).cross(tri[2] - tri[0]).normalize();
+            assert!(normals.iter().all(|n| n.abs_diff_eq(expected, 1e-6)));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let mut bytes = Vec::new();
+        write_stl(&test_mesh(), &mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 10);
+        assert!(read_stl(bytes.as_slice()).is_err());
+    }
+}
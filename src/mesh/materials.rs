@@ -0,0 +1,213 @@
+use super::{IsoMesh, MeshOptions, NULL_MESH_VERTEX_ID};
+use crate::{
+    central_gradient,
+    contour_octree::{contour_work, minimal_edge, visit_edge_polygon},
+    material::Materials,
+    Cell, CellId, CellOctree, ContourVisitor, ContourWork, Edge, MaterialId,
+};
+use glam::Vec3A;
+
+/// The boundaries between all materials, from
+/// [`CellOctree::contour_materials`].
+#[derive(Clone, Debug, Default)]
+pub struct MaterialMesh {
+    pub mesh: IsoMesh,
+    /// The materials `[back, front]` on either side of each triangle, where
+    /// the triangle faces `front`. The front material always has the smaller
+    /// ID, so solids face [`AIR`](crate::AIR).
+    pub tri_materials: Vec<[MaterialId; 2]>,
+    /// Likewise for each quad, if [`MeshOptions::keep_quads`] is set.
+    pub quad_materials: Vec<[MaterialId; 2]>,
+}
+
+impl CellOctree {
+    /// Contour every boundary between two materials, solid or air, of an
+    /// octree built by [`Self::build_multi_material`] with the same
+    /// `materials`.
+    ///
+    /// Each leaf has one vertex, shared by all boundaries through it. Its
+    /// normal is estimated from the boundary between the two materials
+    /// nearest to it, so vertices at junctions rely on
+    /// [`MeshOptions::sharp_normal_threshold`] for the other boundaries.
+    ///
+    /// # Panics
+    ///
+    /// If the octree has no [`Self::corner_materials`], or if `materials` is
+    /// empty or has [`MaterialId::MAX`] or more SDFs.
+    pub fn contour_materials(
+        &self,
+        options: &MeshOptions,
+        materials: &[&dyn Fn(Vec3A) -> f32],
+    ) -> MaterialMesh {
+        let mut out = MaterialMesh::default();
+        if self.all_cells.is_empty() {
+            return out;
+        }
+        assert!(
+            !self.corner_materials.is_empty(),
+            "octree was not built with materials"
+        );
+        let materials = Materials::new(materials);
+        let cells = &self.all_cells;
+        let mesh = &mut out.mesh;
+
+        let mut cell_vertex_ids = vec![NULL_MESH_VERTEX_ID; cells.len()];
+        let mut cell_stack = vec![self.root_id];
+        while let Some(cell_id) = cell_stack.pop() {
+            let cell = &cells[cell_id as usize];
+            if !cell.is_leaf {
+                cell_stack.extend(cell.children.iter().flatten());
+                continue;
+            }
+            let p = Vec3A::from(cell.vertex_estimate);
+            let [(m1, _), (m2, _)] = materials.nearest_two(p);
+            let boundary = materials.boundary(m1.max(m2), m1.min(m2));
+            let n = central_gradient(boundary, p, options.normal_delta).normalize();
+            cell_vertex_ids[cell_id as usize] = mesh.push_vertex(p, n, cell_id);
+        }
+
+        let mut visitor = MaterialVisitor {
+            cells,
+            corner_materials: &self.corner_materials,
+            polygons: MaterialPolygons::default(),
+        };
+        let mut stack = vec![ContourWork::Cell(self.root_id)];
+        while let Some(work) = stack.pop() {
+            contour_work(cells, &mut stack, work, &mut visitor);
+        }
+        let polygons = visitor.polygons;
+
+        let to_vertex = |cell_id: CellId| cell_vertex_ids[cell_id as usize];
+        for (tri, pair) in polygons.triangles {
+            mesh.tri_indices.extend(tri.map(to_vertex));
+            out.tri_materials.push(pair);
+        }
+        for (q, pair) in polygons.quads {
+            // Reorder from Z order into a cycle.
+            let quad = [q[0], q[2], q[3], q[1]].map(to_vertex);
+            if options.keep_quads {
+                mesh.quad_indices.extend_from_slice(&quad);
+                out.quad_materials.push(pair);
+            } else {
+                let num_indices = mesh.tri_indices.len();
+                let [back, front] = pair;
+                mesh.triangulate_quad(
+                    quad,
+                    options.triangulation,
+                    materials.boundary(back, front),
+                    options.normal_delta,
+                );
+                let num_tris = (mesh.tri_indices.len() - num_indices) / 3;
+                let len = out.tri_materials.len() + num_tris;
+                out.tri_materials.resize(len, pair);
+            }
+        }
+
+        mesh.finish(options);
+        out
+    }
+}
+
+/// Polygons with the materials `[back, front]` on either side.
+#[derive(Default)]
+struct MaterialPolygons {
+    pair: [MaterialId; 2],
+    quads: Vec<([CellId; 4], [MaterialId; 2])>,
+    triangles: Vec<([CellId; 3], [MaterialId; 2])>,
+}
+
+impl ContourVisitor for MaterialPolygons {
+    fn visit_leaf(&mut self, _cell_id: CellId, _cell: &mut Cell) {}
+
+    fn visit_quad(&mut self, cells: [CellId; 4]) {
+        self.quads.push((cells, self.pair));
+    }
+
+    fn visit_triangle(&mut self, cells: [CellId; 3]) {
+        self.triangles.push((cells, self.pair));
+    }
+}
+
+/// Collects the polygons of the leaf edges whose ends have different
+/// materials.
+struct MaterialVisitor<'a> {
+    cells: &'a [Cell],
+    corner_materials: &'a [[MaterialId; 8]],
+    polygons: MaterialPolygons,
+}
+
+impl ContourVisitor for MaterialVisitor<'_> {
+    fn visit_leaf(&mut self, _cell_id: CellId, _cell: &mut Cell) {}
+
+    // The boundary with air is handled like any other.
+    fn visit_quad(&mut self, _cells: [CellId; 4]) {}
+
+    fn visit_triangle(&mut self, _cells: [CellId; 3]) {}
+
+    fn visit_edge(&mut self, edge: &Edge) {
+        let edge_cells = edge.cells.map(|id| &self.cells[id as usize]);
+        if !edge_cells.iter().all(|c| c.is_leaf) {
+            return;
+        }
+        let minimal = minimal_edge(*edge, edge_cells);
+        let corner_materials = &self.corner_materials[minimal.cell_id as usize];
+        let [m0, m1] = minimal.corners.map(|c| corner_materials[c as usize]);
+        if m0 == m1 {
+            return;
+        }
+        // Face the material with the smaller ID.
+        self.polygons.pair = [m0.max(m1), m0.min(m1)];
+        visit_edge_polygon(edge, m1 < m0, &mut self.polygons);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives::sphere, BuildOptions, AIR};
+    use ilattice::extent::Extent;
+    use std::collections::HashMap;
+
+    #[test]
+    fn boundaries_face_smaller_material() {
+        // A sphere split into material 1 at x < s and material 2 at x > s,
+        // off the sample grid so no samples are on the split.
+        let s = 0.01;
+        let left = |p: Vec3A| sphere(0.5, p).max(p.x - s);
+        let right = |p: Vec3A| sphere(0.5, p).max(s - p.x);
+        let materials: [&dyn Fn(Vec3A) -> f32; 2] = [&left, &right];
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let options = BuildOptions {
+            max_depth: 5,
+            ..Default::default()
+        };
+        let octree = CellOctree::build_multi_material(root, &options, &materials).unwrap();
+        let options = MeshOptions {
+            sharp_normal_threshold: None,
+            ..Default::default()
+        };
+        let out = octree.contour_materials(&options, &materials);
+        let mesh = &out.mesh;
+        assert_eq!(out.tri_materials.len(), mesh.tri_indices.len() / 3);
+
+        let mut areas = HashMap::new();
+        for (tri, &pair) in mesh.tri_indices.chunks_exact(3).zip(&out.tri_materials) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+            let normal = (b - a).cross(c - a);
+            let centroid = (a + b + c) / 3.0;
+            match pair {
+                [1, AIR] => assert!(centroid.x < s + 0.05 && normal.dot(centroid) > 0.0),
+                [2, AIR] => assert!(centroid.x > s - 0.05 && normal.dot(centroid) > 0.0),
+                [2, 1] => assert!((centroid.x - s).abs() < 0.05 && normal.x < 0.0),
+                _ => panic!("unexpected pair {pair:?}"),
+            }
+            *areas.entry(pair).or_insert(0.0) += 0.5 * normal.length();
+        }
+        // Nearly hemispheres, and the disk between them.
+        let hemisphere = 2.0 * std::f32::consts::PI * 0.25;
+        let disk = std::f32::consts::PI * 0.25;
+        assert!((areas[&[1, AIR]] - hemisphere).abs() < 0.05 * hemisphere);
+        assert!((areas[&[2, AIR]] - hemisphere).abs() < 0.05 * hemisphere);
+        assert!((areas[&[2, 1]] - disk).abs() < 0.05 * disk);
+    }
+}
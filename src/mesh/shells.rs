@@ -0,0 +1,216 @@
+use super::{add_missing_vertices, IsoMesh, MeshOptions, NULL_MESH_VERTEX_ID};
+use crate::{
+    cell_is_bipolar, central_gradient,
+    contour_octree::{contour_work, minimal_edge, visit_edge_polygon},
+    estimate_interior_vertex_qef, Cell, CellId, CellOctree, ContourPolygons, ContourVisitor,
+    ContourWork, Edge,
+};
+use glam::Vec3A;
+
+impl CellOctree {
+    /// Contour several level sets of `sdf` in one traversal, returning one
+    /// mesh per value of `iso_values`.
+    ///
+    /// The octree only resolves the level sets that it was built for (see
+    /// [`BuildOptions::shell_iso_values`](crate::BuildOptions::shell_iso_values)).
+    /// Leaves on the octree's own level set keep their vertices. Other shells
+    /// get vertices by minimizing a QEF with the given `precision`, as in
+    /// [`BuildOptions::precision`](crate::BuildOptions::precision).
+    pub fn contour_shells(
+        &self,
+        iso_values: &[f32],
+        precision: f32,
+        options: &MeshOptions,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> Vec<IsoMesh> {
+        let mut meshes = vec![IsoMesh::default(); iso_values.len()];
+        if self.all_cells.is_empty() {
+            return meshes;
+        }
+        let cells = &self.all_cells;
+        let levels: Vec<_> = iso_values.iter().map(|v| v - self.iso_value).collect();
+
+        let mut cell_vertex_ids = vec![vec![NULL_MESH_VERTEX_ID; cells.len()]; levels.len()];
+        let mut cell_stack = vec![self.root_id];
+        while let Some(cell_id) = cell_stack.pop() {
+            let cell = &cells[cell_id as usize];
+            if !cell.is_leaf {
+                cell_stack.extend(cell.children.iter().flatten());
+                continue;
+            }
+            for ((&level, mesh), vertex_ids) in
+                levels.iter().zip(&mut meshes).zip(&mut cell_vertex_ids)
+            {
+                let samples = cell.samples.map(|s| s - level);
+                if !cell_is_bipolar(&samples) {
+                    continue;
+                }
+                let p = if level == 0.0 {
+                    Vec3A::from(cell.vertex_estimate)
+                } else {
+                    let (regularized_qef, _) =
+                        estimate_interior_vertex_qef(&cell.extent, &samples, &sdf, precision);
+                    regularized_qef.minimizer()
+                };
+                let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+                vertex_ids[cell_id as usize] = mesh.push_vertex(p, n, cell_id);
+            }
+        }
+
+        let mut visitor = ShellVisitor {
+            cells,
+            levels: &levels,
+            polygons: vec![ContourPolygons::default(); levels.len()],
+        };
+        let mut stack = vec![ContourWork::Cell(self.root_id)];
+        while let Some(work) = stack.pop() {
+            contour_work(cells, &mut stack, work, &mut visitor);
+        }
+
+        for ((((mesh, polygons), vertex_ids), &iso_value), &level) in meshes
+            .iter_mut()
+            .zip(visitor.polygons)
+            .zip(&mut cell_vertex_ids)
+            .zip(iso_values)
+            .zip(&levels)
+        {
+            add_missing_vertices(&polygons, vertex_ids, |cell_id| {
+                let cell = &cells[cell_id as usize];
+                let p = if level == 0.0 {
+                    Vec3A::from(cell.vertex_estimate)
+                } else {
+                    cell.extent.center()
+                };
+                let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+                mesh.push_vertex(p, n, cell_id)
+            });
+            mesh.add_polygons(polygons, vertex_ids, options, |p| sdf(p) - iso_value);
+        }
+        meshes
+    }
+}
+
+/// Collects the polygons of each shell from the leaf edges.
+struct ShellVisitor<'a> {
+    cells: &'a [Cell],
+    /// Iso values relative to the octree's.
+    levels: &'a [f32],
+    polygons: Vec<ContourPolygons>,
+}
+
+impl ContourVisitor for ShellVisitor<'_> {
+    fn visit_leaf(&mut self, _cell_id: CellId, _cell: &mut Cell) {}
+
+    // The octree's own surface is handled like any other shell.
+    fn visit_quad(&mut self, _cells: [CellId; 4]) {}
+
+    fn visit_triangle(&mut self, _cells: [CellId; 3]) {}
+
+    fn visit_edge(&mut self, edge: &Edge) {
+        let edge_cells = edge.cells.map(|id| &self.cells[id as usize]);
+        if !edge_cells.iter().all(|c| c.is_leaf) {
+            return;
+        }
+        let [d0, d1] = minimal_edge(*edge, edge_cells).samples;
+        for (&level, polygons) in self.levels.iter().zip(&mut self.polygons) {
+            let flip = match (d0 < level, d1 < level) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => continue,
+            };
+            visit_edge_polygon(edge, flip, polygons);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives::sphere, BuildOptions};
+    use ilattice::extent::Extent;
+
+    fn welded() -> MeshOptions {
+        MeshOptions {
+            sharp_normal_threshold: None,
+            ..Default::default()
+        }
+    }
+
+    fn root() -> Extent<Vec3A> {
+        Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0))
+    }
+
+    fn triangles(mesh: &IsoMesh) -> impl Iterator<Item = [Vec3A; 3]> + '_ {
+        mesh.tri_indices
+            .chunks_exact(3)
+            .map(|t| [0, 1, 2].map(|i| mesh.positions[t[i] as usize]))
+    }
+
+    fn signed_volume(mesh: &IsoMesh) -> f32 {
+        triangles(mesh)
+            .map(|[a, b, c]| a.dot(b.cross(c)) / 6.0)
+            .sum()
+    }
+
+    /// The smallest and largest distances from the origin of the vertices
+    /// and triangle centroids.
+    fn radius_range(mesh: &IsoMesh) -> (f32, f32) {
+        let centroids = triangles(mesh).map(|[a, b, c]| (a + b + c) / 3.0);
+        mesh.positions
+            .iter()
+            .copied()
+            .chain(centroids)
+            .map(Vec3A::length)
+            .fold((f32::MAX, 0.0), |(lo, hi), r| (lo.min(r), hi.max(r)))
+    }
+
+    #[test]
+    fn iso_value_offsets_sphere() {
+        let sdf = |p| sphere(0.4, p);
+        for iso_value in [-0.2, 0.0, 0.25] {
+            let options = BuildOptions {
+                max_depth: 5,
+                iso_value,
+                ..Default::default()
+            };
+            let mut octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+            let mesh = octree.contour_to_mesh(&welded(), sdf);
+            let (lo, hi) = radius_range(&mesh);
+            let r = 0.4 + iso_value;
+            assert!(lo > r - 0.01 && hi < r + 0.01, "{iso_value}: {lo}..{hi}");
+            for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+                assert!(n.dot(p.normalize()) > 0.99);
+            }
+        }
+    }
+
+    #[test]
+    fn shells_are_nested() {
+        let sdf = |p| sphere(0.4, p);
+        let iso_values = [-0.15, 0.0, 0.15, 0.3];
+        let options = BuildOptions {
+            max_depth: 5,
+            shell_iso_values: iso_values.to_vec(),
+            ..Default::default()
+        };
+        let octree = CellOctree::build_with_options(root(), &options, sdf).unwrap();
+        let shells = octree.contour_shells(&iso_values, 0.1, &welded(), sdf);
+
+        let mut prev_hi = 0.0;
+        let mut prev_volume = 0.0;
+        for (mesh, iso_value) in shells.iter().zip(iso_values) {
+            let (lo, hi) = radius_range(mesh);
+            let r = 0.4 + iso_value;
+            assert!(lo > r - 0.02 && hi < r + 0.02, "{iso_value}: {lo}..{hi}");
+            // Disjoint radii mean that the shells don't intersect.
+            assert!(lo > prev_hi);
+            prev_hi = hi;
+
+            let volume = signed_volume(mesh);
+            let expected = 4.0 / 3.0 * std::f32::consts::PI * r.powi(3);
+            assert!((volume - expected).abs() < 0.05 * expected);
+            assert!(volume > prev_volume);
+            prev_volume = volume;
+        }
+    }
+}
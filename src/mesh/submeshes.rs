@@ -0,0 +1,358 @@
+use super::{add_missing_vertices, IsoMesh, MeshOptions, MeshVertexId, NULL_MESH_VERTEX_ID};
+use crate::{
+    cell_is_bipolar, central_gradient, Cell, CellId, CellOctree, ContourPolygons, MaterialId,
+};
+use glam::Vec3A;
+use std::ops::Range;
+
+/// Assigns materials for [`CellOctree::contour_to_submeshes`].
+pub enum MaterialAssignment<'a> {
+    /// Called with each leaf that has a vertex. Each polygon takes the most
+    /// common material of its vertices, or the smallest of those on a tie.
+    PerVertex(&'a mut dyn FnMut(CellId, &Cell) -> MaterialId),
+    /// Called with the leaves of each polygon's vertices and their positions,
+    /// in winding order.
+    PerPolygon(&'a mut dyn FnMut(&[CellId], &[Vec3A]) -> MaterialId),
+}
+
+/// What to do with vertices whose polygons have different materials.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SeamPolicy {
+    /// All submeshes reference the same vertex, so the mesh stays connected.
+    #[default]
+    Shared,
+    /// Each submesh gets its own copy, so submeshes don't share any vertices
+    /// and can be drawn or exported separately.
+    Duplicated,
+}
+
+/// The polygons of one material in a [`SubmeshedMesh`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Submesh {
+    pub material: MaterialId,
+    /// The range of [`IsoMesh::tri_indices`] with this material.
+    pub tri_indices: Range<usize>,
+    /// The range of [`IsoMesh::quad_indices`] with this material.
+    pub quad_indices: Range<usize>,
+}
+
+/// A mesh whose polygons are grouped by material, from
+/// [`CellOctree::contour_to_submeshes`].
+#[derive(Clone, Debug, Default)]
+pub struct SubmeshedMesh {
+    pub mesh: IsoMesh,
+    /// Sorted by material, without empty submeshes.
+    pub submeshes: Vec<Submesh>,
+}
+
+impl SubmeshedMesh {
+    /// The triangle indices of `submesh`.
+    pub fn tri_indices(&self, submesh: &Submesh) -> &[MeshVertexId] {
+        &self.mesh.tri_indices[submesh.tri_indices.clone()]
+    }
+
+    /// The quad indices of `submesh`.
+    pub fn quad_indices(&self, submesh: &Submesh) -> &[MeshVertexId] {
+        &self.mesh.quad_indices[submesh.quad_indices.clone()]
+    }
+}
+
+impl CellOctree {
+    /// Like [`Self::contour_to_mesh`], but with one index buffer per
+    /// material.
+    ///
+    /// Vertices on seams between materials are shared or duplicated
+    /// according to `seams`. Normals are repaired after splitting, so
+    /// [`SeamPolicy::Duplicated`] vertices may be split further.
+    pub fn contour_to_submeshes(
+        &mut self,
+        options: &MeshOptions,
+        seams: SeamPolicy,
+        sdf: impl Fn(Vec3A) -> f32,
+        assignment: MaterialAssignment,
+    ) -> SubmeshedMesh {
+        let sdf = self.surface_sdf(sdf);
+        let mut vertices = IsoMesh::default();
+        let mut vertex_materials = Vec::new();
+        let mut polygons = ContourPolygons::default();
+        let mut vertex_material = match assignment {
+            MaterialAssignment::PerVertex(f) => Ok(f),
+            MaterialAssignment::PerPolygon(f) => Err(f),
+        };
+
+        let mut cell_vertex_ids = vec![NULL_MESH_VERTEX_ID; self.all_cells.len()];
+        self.dual_contour(
+            |cell_id, cell| {
+                if !cell_is_bipolar(&cell.samples) {
+                    return;
+                }
+                let p = Vec3A::from(cell.vertex_estimate);
+                let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+                cell_vertex_ids[cell_id as usize] = vertices.push_vertex(p, n, cell_id);
+                if let Ok(f) = &mut vertex_material {
+                    vertex_materials.push(f(cell_id, cell));
+                }
+            },
+            |q| polygons.quads.push(q),
+            |tri| polygons.triangles.push(tri),
+        );
+        add_missing_vertices(&polygons, &mut cell_vertex_ids, |cell_id| {
+            let cell = &self.all_cells[cell_id as usize];
+            let p = Vec3A::from(cell.vertex_estimate);
+            let n = central_gradient(&sdf, p, options.normal_delta).normalize();
+            if let Ok(f) = &mut vertex_material {
+                vertex_materials.push(f(cell_id, cell));
+            }
+            vertices.push_vertex(p, n, cell_id)
+        });
+
+        // Assign and group the polygons by material, with triangles before
+        // quads like `contour_to_mesh`.
+        let triangles = polygons.triangles.into_iter().map(Polygon::Triangle);
+        // Reorder from Z order into a cycle.
+        let quads = polygons
+            .quads
+            .into_iter()
+            .map(|q| Polygon::Quad([q[0], q[2], q[3], q[1]]));
+        let mut by_material: Vec<(MaterialId, Polygon)> = triangles
+            .chain(quads)
+            .map(|polygon| {
+                let cells = polygon.cells();
+                let material = match &mut vertex_material {
+                    Ok(_) => majority(
+                        cells
+                            .iter()
+                            .map(|&c| vertex_materials[cell_vertex_ids[c as usize] as usize]),
+                    ),
+                    Err(f) => {
+                        let positions: Vec<_> = cells
+                            .iter()
+                            .map(|&c| vertices.positions[cell_vertex_ids[c as usize] as usize])
+                            .collect();
+                        f(cells, &positions)
+                    }
+                };
+                (material, polygon)
+            })
+            .collect();
+        by_material.sort_by_key(|&(m, _)| m);
+
+        let mut out = SubmeshedMesh::default();
+        let mesh = &mut out.mesh;
+        if seams == SeamPolicy::Shared {
+            *mesh = std::mem::take(&mut vertices);
+        }
+        let iso_value = self.iso_value;
+        // Mesh vertex IDs of the current submesh, indexed by shared vertex ID.
+        let mut copies = vec![NULL_MESH_VERTEX_ID; vertices.positions.len()];
+
+        let mut rest = by_material.as_slice();
+        while let Some(&(material, _)) = rest.first() {
+            let len = rest.iter().take_while(|(m, _)| *m == material).count();
+            let (group, tail) = rest.split_at(len);
+            rest = tail;
+            let tri_start = mesh.tri_indices.len();
+            let quad_start = mesh.quad_indices.len();
+            copies.fill(NULL_MESH_VERTEX_ID);
+
+            for (_, polygon) in group {
+                let mut to_vertex = |cell_id: CellId| {
+                    let v = cell_vertex_ids[cell_id as usize];
+                    if seams == SeamPolicy::Shared {
+                        return v;
+                    }
+                    let copy = &mut copies[v as usize];
+                    if *copy == NULL_MESH_VERTEX_ID {
+                        let v = v as usize;
+                        *copy = mesh.push_vertex(
+                            vertices.positions[v],
+                            vertices.normals[v],
+                            vertices.cell_ids[v],
+                        );
+                    }
+                    *copy
+                };
+                match *polygon {
+                    Polygon::Triangle(t) => {
+                        let tri = t.map(&mut to_vertex);
+                        mesh.tri_indices.extend_from_slice(&tri);
+                    }
+                    Polygon::Quad(q) => {
+                        let quad = q.map(&mut to_vertex);
+                        if options.keep_quads {
+                            mesh.quad_indices.extend_from_slice(&quad);
+                        } else {
+                            mesh.triangulate_quad(
+                                quad,
+                                options.triangulation,
+                                |p| sdf(p) - iso_value,
+                                options.normal_delta,
+                            );
+                        }
+                    }
+                }
+            }
+
+            out.submeshes.push(Submesh {
+                material,
+                tri_indices: tri_start..mesh.tri_indices.len(),
+                quad_indices: quad_start..mesh.quad_indices.len(),
+            });
+        }
+
+        mesh.finish(options);
+        out
+    }
+}
+
+enum Polygon {
+    Triangle([CellId; 3]),
+    /// In winding order.
+    Quad([CellId; 4]),
+}
+
+impl Polygon {
+    fn cells(&self) -> &[CellId] {
+        match self {
+            Self::Triangle(t) => t,
+            Self::Quad(q) => q,
+        }
+    }
+}
+
+/// The most common material, or the smallest of those on a tie.
+fn majority(materials: impl Iterator<Item = MaterialId>) -> MaterialId {
+    let mut counts: Vec<(MaterialId, u8)> = Vec::with_capacity(4);
+    for m in materials {
+        match counts.iter_mut().find(|(c, _)| *c == m) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((m, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(m, n)| (n, std::cmp::Reverse(m)))
+        .map_or(0, |(m, _)| m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives::sphere;
+    use ilattice::extent::Extent;
+
+    fn sdf(p: Vec3A) -> f32 {
+        sphere(0.6, p)
+    }
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        CellOctree::build(root, 5, 0.0001, 0.1, sdf).unwrap()
+    }
+
+    fn options(keep_quads: bool) -> MeshOptions {
+        MeshOptions {
+            sharp_normal_threshold: None,
+            keep_quads,
+            ..Default::default()
+        }
+    }
+
+    /// Material 3 below the XY plane and 1 above it, from the centroid.
+    fn by_height(_cells: &[CellId], positions: &[Vec3A]) -> MaterialId {
+        let z = positions.iter().map(|p| p.z).sum::<f32>();
+        if z < 0.0 {
+            3
+        } else {
+            1
+        }
+    }
+
+    fn contour(keep_quads: bool, seams: SeamPolicy) -> SubmeshedMesh {
+        octree().contour_to_submeshes(
+            &options(keep_quads),
+            seams,
+            sdf,
+            MaterialAssignment::PerPolygon(&mut by_height),
+        )
+    }
+
+    #[test]
+    fn submeshes_partition_indices() {
+        for keep_quads in [false, true] {
+            let expected = octree().contour_to_mesh(&options(keep_quads), sdf);
+            let out = contour(keep_quads, SeamPolicy::Shared);
+            let mesh = &out.mesh;
+            assert_eq!(mesh.positions.len(), expected.positions.len());
+            assert_eq!(mesh.tri_indices.len(), expected.tri_indices.len());
+            assert_eq!(mesh.quad_indices.len(), expected.quad_indices.len());
+
+            let materials: Vec<_> = out.submeshes.iter().map(|s| s.material).collect();
+            assert_eq!(materials, [1, 3]);
+            let [a, b] = [&out.submeshes[0], &out.submeshes[1]];
+            assert_eq!(a.tri_indices.start, 0);
+            assert_eq!(a.tri_indices.end, b.tri_indices.start);
+            assert_eq!(b.tri_indices.end, mesh.tri_indices.len());
+            assert_eq!(a.quad_indices.start, 0);
+            assert_eq!(a.quad_indices.end, b.quad_indices.start);
+            assert_eq!(b.quad_indices.end, mesh.quad_indices.len());
+            assert_eq!(a.quad_indices.is_empty(), !keep_quads);
+
+            if !keep_quads {
+                // Triangles from quads don't know the quad's centroid.
+                continue;
+            }
+            for submesh in &out.submeshes {
+                let tris = out.tri_indices(submesh).chunks_exact(3);
+                let quads = out.quad_indices(submesh).chunks_exact(4);
+                for polygon in tris.chain(quads) {
+                    let positions: Vec<_> = polygon
+                        .iter()
+                        .map(|&v| mesh.positions[v as usize])
+                        .collect();
+                    assert_eq!(by_height(&[], &positions), submesh.material);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn duplicated_seams_split_vertices() {
+        let shared = contour(true, SeamPolicy::Shared);
+        let duplicated = contour(true, SeamPolicy::Duplicated);
+        assert_eq!(duplicated.submeshes, shared.submeshes);
+
+        let used = |out: &SubmeshedMesh, submesh: &Submesh| {
+            let mut used = vec![false; out.mesh.positions.len()];
+            let tris = out.tri_indices(submesh);
+            for &v in tris.iter().chain(out.quad_indices(submesh)) {
+                used[v as usize] = true;
+            }
+            used
+        };
+        let [a, b] = [0, 1].map(|i| used(&shared, &shared.submeshes[i]));
+        let num_seam_vertices = a.iter().zip(&b).filter(|(a, b)| **a && **b).count();
+        assert!(num_seam_vertices > 0);
+        assert_eq!(
+            duplicated.mesh.positions.len(),
+            shared.mesh.positions.len() + num_seam_vertices
+        );
+
+        let [a, b] = [0, 1].map(|i| used(&duplicated, &duplicated.submeshes[i]));
+        assert!(a.iter().zip(&b).all(|(a, b)| !(*a && *b)));
+        // The same polygons, with copies of the same vertices.
+        let corners = |out: &SubmeshedMesh| -> Vec<Vec3A> {
+            let mesh = &out.mesh;
+            let indices = mesh.tri_indices.iter().chain(&mesh.quad_indices);
+            indices.map(|&v| mesh.positions[v as usize]).collect()
+        };
+        assert_eq!(corners(&duplicated), corners(&shared));
+    }
+
+    #[test]
+    fn majority_prefers_smaller_on_tie() {
+        assert_eq!(majority([4, 2, 2, 4].into_iter()), 2);
+        assert_eq!(majority([5, 3, 3].into_iter()), 3);
+        assert_eq!(majority([7].into_iter()), 7);
+    }
+}
@@ -0,0 +1,244 @@
+use super::{IsoMesh, MeshVertexId};
+use glam::{Vec2, Vec3A, Vec4};
+
+impl IsoMesh {
+    /// Replace [`Self::tangents`] using [`generate_tangents`]. Split vertices
+    /// keep their `cell_ids`.
+    ///
+    /// Does nothing without UVs and normals.
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        if self.uvs.is_empty() || self.normals.is_empty() {
+            return;
+        }
+        let cell_ids = &mut self.cell_ids;
+        let polygons = self
+            .tri_indices
+            .chunks_exact_mut(3)
+            .chain(self.quad_indices.chunks_exact_mut(4));
+        self.tangents = generate_tangents_with(
+            polygons,
+            &mut self.positions,
+            &mut self.normals,
+            &mut self.uvs,
+            |v| cell_ids.push(cell_ids[v as usize]),
+        );
+    }
+}
+
+/// Generate a tangent for every vertex from the UVs of the triangles and
+/// quads.
+///
+/// Each triangle's `+u` direction is projected onto the normal at each
+/// corner and weighted by the corner's angle. Quads are split like
+/// [`Triangulation::FixedDiagonal`](super::Triangulation), but both halves
+/// take the handedness of the whole quad. Only corners that share a
+/// vertex and the handedness of their UVs are merged, so a vertex whose
+/// triangles are mirrored in UV space is split. Vertices split by
+/// [`repair_sharp_normals`](super::repair_sharp_normals) or UV seams keep
+/// separate tangents, so run this after them.
+///
+/// XYZ of each tangent is the tangent direction and W is `1` or `-1` for the
+/// handedness of the bitangent, so that
+/// `bitangent = w * normal.cross(tangent)`.
+pub fn generate_tangents(
+    tri_indices: &mut [u32],
+    quad_indices: &mut [u32],
+    positions: &mut Vec<Vec3A>,
+    normals: &mut Vec<Vec3A>,
+    uvs: &mut Vec<Vec2>,
+) -> Vec<Vec4> {
+    let polygons = tri_indices
+        .chunks_exact_mut(3)
+        .chain(quad_indices.chunks_exact_mut(4));
+    generate_tangents_with(polygons, positions, normals, uvs, |_| {})
+}
+
+/// Calls `split_vertex` with the original vertex every time a new vertex is
+/// added.
+fn generate_tangents_with<'a>(
+    polygons: impl Iterator<Item = &'a mut [MeshVertexId]>,
+    positions: &mut Vec<Vec3A>,
+    normals: &mut Vec<Vec3A>,
+    uvs: &mut Vec<Vec2>,
+    mut split_vertex: impl FnMut(MeshVertexId),
+) -> Vec<Vec4> {
+    // The handedness of the first polygon to use each vertex, and the vertex
+    // split off for the other handedness.
+    let mut handedness: Vec<Option<bool>> = vec![None; positions.len()];
+    let mut mirrored: Vec<Option<MeshVertexId>> = vec![None; positions.len()];
+    let mut sum_tangents = vec![Vec3A::ZERO; positions.len()];
+
+    for polygon in polygons {
+        let tris: &[[usize; 3]] = if polygon.len() == 4 {
+            &[[0, 1, 3], [1, 2, 3]]
+        } else {
+            &[[0, 1, 2]]
+        };
+        // The derivative of position along `u`, times the UV area, for each
+        // triangle.
+        let mut tri_tangents = [(Vec3A::ZERO, 0.0); 2];
+        for (tri, tangent) in tris.iter().zip(&mut tri_tangents) {
+            let p = tri.map(|c| positions[polygon[c] as usize]);
+            let uv = tri.map(|c| uvs[polygon[c] as usize]);
+            let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+            let area = d1.perp_dot(d2);
+            *tangent = ((p[1] - p[0]) * d2.y - (p[2] - p[0]) * d1.y, area);
+        }
+        let preserves_orientation = tri_tangents.iter().map(|t| t.1).sum::<f32>() >= 0.0;
+
+        // Assign each corner to the vertex with its handedness.
+        for v in polygon.iter_mut() {
+            let old = *v as usize;
+            match handedness[old] {
+                None => handedness[old] = Some(preserves_orientation),
+                Some(h) if h == preserves_orientation => {}
+                Some(_) => {
+                    if mirrored[old].is_none() {
+                        mirrored[old] = Some(positions.len() as MeshVertexId);
+                        positions.push(positions[old]);
+                        normals.push(normals[old]);
+                        uvs.push(uvs[old]);
+                        split_vertex(old as MeshVertexId);
+                        handedness.push(Some(preserves_orientation));
+                        mirrored.push(None);
+                        sum_tangents.push(Vec3A::ZERO);
+                    }
+                    *v = mirrored[old].unwrap();
+                }
+            }
+        }
+
+        for (tri, &(tangent, area)) in tris.iter().zip(&tri_tangents) {
+            if area == 0.0 {
+                continue;
+            }
+            let tangent = tangent * area.signum();
+            for (i, &c) in tri.iter().enumerate() {
+                let v = polygon[c] as usize;
+                let n = normals[v];
+                let project = |d: Vec3A| (d - n * n.dot(d)).normalize_or_zero();
+                let t = project(tangent);
+                // The angle of the corner, in the tangent plane.
+                let p = positions[v];
+                let e1 = project(positions[polygon[tri[(i + 1) % 3]] as usize] - p);
+                let e2 = project(positions[polygon[tri[(i + 2) % 3]] as usize] - p);
+                let angle = e1.dot(e2).clamp(-1.0, 1.0).acos();
+                sum_tangents[v] += angle * t;
+            }
+        }
+    }
+
+    sum_tangents
+        .into_iter()
+        .zip(normals.iter())
+        .zip(handedness)
+        .map(|((t, &n), handedness)| {
+            let mut t = (t - n * n.dot(t)).normalize_or_zero();
+            if t == Vec3A::ZERO {
+                t = n.any_orthonormal_vector();
+            }
+            let w = if handedness.unwrap_or(true) {
+                1.0
+            } else {
+                -1.0
+            };
+            t.extend(w)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives::sphere, CellOctree, MeshOptions, UvProjection};
+    use glam::Vec3;
+    use ilattice::extent::Extent;
+
+    /// Two unit quads in the XY plane facing `+Z`, sharing the edge at
+    /// `x = 1`, with `u` mirrored across that edge and `v = y`.
+    fn mirrored_quads() -> IsoMesh {
+        let grid = |x, y| Vec3A::new(x, y, 0.0);
+        IsoMesh {
+            positions: vec![
+                grid(0.0, 0.0),
+                grid(1.0, 0.0),
+                grid(2.0, 0.0),
+                grid(0.0, 1.0),
+                grid(1.0, 1.0),
+                grid(2.0, 1.0),
+            ],
+            normals: vec![Vec3A::Z; 6],
+            cell_ids: (0..6).collect(),
+            quad_indices: vec![0, 1, 4, 3, 1, 2, 5, 4],
+            uvs: [0.0, 1.0, 0.0, 0.0, 1.0, 0.0]
+                .iter()
+                .zip([0.0, 0.0, 0.0, 1.0, 1.0, 1.0])
+                .map(|(&u, v)| Vec2::new(u, v))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_vertices() {
+        let mut mesh = mirrored_quads();
+        mesh.generate_tangents();
+        assert_eq!(mesh.positions.len(), 8);
+        assert_eq!(mesh.tangents.len(), 8);
+        assert_eq!(mesh.cell_ids[6..], [1, 4]);
+        assert_eq!(mesh.quad_indices, [0, 1, 4, 3, 6, 2, 5, 7]);
+
+        for (v, tangent) in mesh.tangents.iter().enumerate() {
+            let left = mesh.quad_indices[..4].contains(&(v as u32));
+            let expected = if left {
+                Vec4::new(1.0, 0.0, 0.0, 1.0)
+            } else {
+                Vec4::new(-1.0, 0.0, 0.0, -1.0)
+            };
+            assert!((*tangent - expected).length() < 1e-5, "{v}: {tangent}");
+            // The bitangent follows `+v` on both sides.
+            let bitangent = tangent.w * Vec3::Z.cross(tangent.truncate());
+            assert!((bitangent - Vec3::Y).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn free_function_takes_quads() {
+        let mut mesh = mirrored_quads();
+        let tangents = generate_tangents(
+            &mut mesh.tri_indices,
+            &mut mesh.quad_indices,
+            &mut mesh.positions,
+            &mut mesh.normals,
+            &mut mesh.uvs,
+        );
+        let mut expected = mirrored_quads();
+        expected.generate_tangents();
+        assert_eq!(tangents, expected.tangents);
+        assert_eq!(mesh.quad_indices, expected.quad_indices);
+        assert_eq!(mesh.positions, expected.positions);
+        assert_eq!(mesh.uvs, expected.uvs);
+    }
+
+    #[test]
+    fn tangents_are_orthonormal_to_normals() {
+        let sdf = |p| sphere(0.6, p);
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let mut octree = CellOctree::build(root, 5, 0.0001, 0.1, sdf).unwrap();
+        let options = MeshOptions {
+            uv_projection: Some(UvProjection::Box { scale: 1.0 }),
+            ..Default::default()
+        };
+        let mesh = octree.contour_to_mesh(&options, sdf);
+        assert_eq!(mesh.tangents.len(), mesh.positions.len());
+        for (t, n) in mesh.tangents.iter().zip(&mesh.normals) {
+            let t3 = Vec3A::from(t.truncate());
+            assert!((t3.length() - 1.0).abs() < 1e-4);
+            assert!(t3.dot(*n).abs() < 1e-4);
+            assert!(t.w == 1.0 || t.w == -1.0);
+        }
+        // Box projection is never mirrored.
+        assert!(mesh.tangents.iter().all(|t| t.w == 1.0));
+    }
+}
@@ -0,0 +1,328 @@
+use super::{IsoMesh, MeshVertexId};
+use glam::{Vec2, Vec3A};
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+/// How to generate [`IsoMesh::uvs`].
+///
+/// In every projection, `u × v` follows the surface normal, so textures are
+/// not mirrored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UvProjection {
+    /// Project each polygon along the axis closest to its normal, with
+    /// `scale` UVs per unit of length. On the side faces, `v` follows `+Y`.
+    Box { scale: f32 },
+    /// `u` is the angle around `axis` through `center`, from 0 to 1, and `v`
+    /// is the height along `axis`, with `scale` UVs per unit of length.
+    Cylindrical {
+        center: Vec3A,
+        axis: Vec3A,
+        scale: f32,
+    },
+    /// `u` is the angle around `axis` through `center`, from 0 to 1, and `v`
+    /// is the latitude, from 0 at the `-axis` pole to 1 at the `+axis` pole.
+    Spherical { center: Vec3A, axis: Vec3A },
+}
+
+impl UvProjection {
+    /// Write the UVs of the corners of a polygon with the given `normal`.
+    fn project_polygon(&self, positions: &[Vec3A], normal: Vec3A, uvs: &mut [Vec2]) {
+        match *self {
+            Self::Box { scale } => {
+                let a = normal.abs();
+                let axis = if a.x >= a.y && a.x >= a.z {
+                    0
+                } else if a.y >= a.z {
+                    1
+                } else {
+                    2
+                };
+                let positive = normal[axis] >= 0.0;
+                for (uv, p) in uvs.iter_mut().zip(positions) {
+                    let [x, y, z] = p.to_array();
+                    let (u, v) = match (axis, positive) {
+                        (0, true) => (-z, y),
+                        (0, false) => (z, y),
+                        (1, true) => (x, -z),
+                        (1, false) => (x, z),
+                        (_, true) => (x, y),
+                        (_, false) => (-x, y),
+                    };
+                    *uv = scale * Vec2::new(u, v);
+                }
+            }
+            Self::Cylindrical {
+                center,
+                axis,
+                scale,
+            } => {
+                let frame = AxisFrame::new(axis);
+                for (uv, &p) in uvs.iter_mut().zip(positions) {
+                    let d = p - center;
+                    *uv = Vec2::new(
+                        frame.angle(d).unwrap_or(f32::NAN),
+                        scale * d.dot(frame.axis),
+                    );
+                }
+                unwrap_angles(uvs);
+            }
+            Self::Spherical { center, axis } => {
+                let frame = AxisFrame::new(axis);
+                for (uv, &p) in uvs.iter_mut().zip(positions) {
+                    let d = (p - center).normalize_or_zero();
+                    let latitude = 1.0 - d.dot(frame.axis).clamp(-1.0, 1.0).acos() / PI;
+                    *uv = Vec2::new(frame.angle(d).unwrap_or(f32::NAN), latitude);
+                }
+                unwrap_angles(uvs);
+            }
+        }
+    }
+}
+
+/// An orthonormal frame around a projection axis.
+struct AxisFrame {
+    axis: Vec3A,
+    e1: Vec3A,
+    e2: Vec3A,
+}
+
+impl AxisFrame {
+    fn new(axis: Vec3A) -> Self {
+        let axis = axis.normalize();
+        let e1 = axis.any_orthonormal_vector();
+        Self {
+            axis,
+            e1,
+            e2: axis.cross(e1),
+        }
+    }
+
+    /// The angle of `d` around the axis in `[0, 1]`, or `None` on the axis.
+    fn angle(&self, d: Vec3A) -> Option<f32> {
+        let (x, y) = (d.dot(self.e1), d.dot(self.e2));
+        if x * x + y * y <= 1e-12 * d.length_squared() {
+            return None;
+        }
+        Some(y.atan2(x) / TAU + 0.5)
+    }
+}
+
+/// Keep the angles in `u` of a polygon from jumping across the seam at 0,
+/// and give corners on the axis (`NaN`) the mean angle of the others.
+fn unwrap_angles(uvs: &mut [Vec2]) {
+    let angles = || uvs.iter().map(|uv| uv.x).filter(|u| !u.is_nan());
+    let min = angles().fold(f32::INFINITY, f32::min);
+    let max = angles().fold(f32::NEG_INFINITY, f32::max);
+    let wrap = max - min > 0.5;
+    let mut sum = 0.0;
+    let mut count = 0;
+    for uv in uvs.iter_mut().filter(|uv| !uv.x.is_nan()) {
+        if wrap && uv.x < 0.5 {
+            uv.x += 1.0;
+        }
+        sum += uv.x;
+        count += 1;
+    }
+    let mean = if count > 0 { sum / count as f32 } else { 0.0 };
+    for uv in uvs.iter_mut().filter(|uv| uv.x.is_nan()) {
+        uv.x = mean;
+    }
+}
+
+impl IsoMesh {
+    /// Replace [`Self::uvs`] with the given projection of every triangle and
+    /// quad.
+    ///
+    /// Vertices whose polygons need different UVs, on seams of the
+    /// projection, are split like [`Self::repair_sharp_normals`] splits
+    /// them. Split vertices keep their `cell_ids`. Any tangents are cleared,
+    /// so call [`Self::generate_tangents`] afterward.
+    pub fn generate_uvs(&mut self, projection: UvProjection) {
+        let Self {
+            positions,
+            normals,
+            cell_ids,
+            tri_indices,
+            quad_indices,
+            uvs,
+            tangents,
+        } = self;
+        tangents.clear();
+
+        let mut assigned = vec![None; positions.len()];
+        let mut splits = HashMap::new();
+        let mut polygon_positions = [Vec3A::ZERO; 4];
+        let mut polygon_uvs = [Vec2::ZERO; 4];
+        let polygons = tri_indices
+            .chunks_exact_mut(3)
+            .chain(quad_indices.chunks_exact_mut(4));
+        for polygon in polygons {
+            let n = polygon.len();
+            for (p, &v) in polygon_positions.iter_mut().zip(polygon.iter()) {
+                *p = positions[v as usize];
+            }
+            let corners = &polygon_positions[..n];
+            projection.project_polygon(corners, polygon_normal(corners), &mut polygon_uvs[..n]);
+
+            for (v, &uv) in polygon.iter_mut().zip(&polygon_uvs) {
+                let old = *v as usize;
+                match assigned[old] {
+                    None => assigned[old] = Some(uv),
+                    Some(a) if a == uv => {}
+                    Some(_) => {
+                        let key = (old, uv.to_array().map(f32::to_bits));
+                        *v = *splits.entry(key).or_insert_with(|| {
+                            let new_vert = positions.len() as MeshVertexId;
+                            positions.push(positions[old]);
+                            if !normals.is_empty() {
+                                normals.push(normals[old]);
+                            }
+                            if !cell_ids.is_empty() {
+                                cell_ids.push(cell_ids[old]);
+                            }
+                            assigned.push(Some(uv));
+                            new_vert
+                        });
+                    }
+                }
+            }
+        }
+
+        *uvs = assigned
+            .into_iter()
+            .map(|uv| uv.unwrap_or(Vec2::ZERO))
+            .collect();
+    }
+}
+
+/// Twice the area vector of a polygon, which also works for non-planar quads.
+pub(super) fn polygon_normal(positions: &[Vec3A]) -> Vec3A {
+    let mut normal = Vec3A::ZERO;
+    for (i, &p) in positions.iter().enumerate() {
+        normal += p.cross(positions[(i + 1) % positions.len()]);
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives, CellOctree, MeshOptions};
+    use ilattice::extent::Extent;
+
+    fn mesh(sdf: impl Fn(Vec3A) -> f32) -> IsoMesh {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let mut octree = CellOctree::build(root, 5, 0.0001, 0.1, &sdf).unwrap();
+        let options = MeshOptions {
+            sharp_normal_threshold: None,
+            ..Default::default()
+        };
+        octree.contour_to_mesh(&options, sdf)
+    }
+
+    fn sphere_mesh() -> IsoMesh {
+        mesh(|p| sdf_primitives::sphere(0.6, p))
+    }
+
+    fn triangles(mesh: &IsoMesh) -> impl Iterator<Item = [usize; 3]> + '_ {
+        mesh.tri_indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]].map(|v| v as usize))
+    }
+
+    /// Twice the signed area of a triangle in UV space, which is positive
+    /// where `u × v` follows the triangle's normal.
+    fn uv_area(mesh: &IsoMesh, [a, b, c]: [usize; 3]) -> f32 {
+        (mesh.uvs[b] - mesh.uvs[a]).perp_dot(mesh.uvs[c] - mesh.uvs[a])
+    }
+
+    /// Check that the mesh is unchanged except for split vertices, and
+    /// that each triangle has the UVs of its own projection.
+    fn assert_split_consistently(before: &IsoMesh, after: &IsoMesh, projection: UvProjection) {
+        assert_eq!(after.uvs.len(), after.positions.len());
+        assert_eq!(after.normals.len(), after.positions.len());
+        assert_eq!(after.cell_ids.len(), after.positions.len());
+        for (old, new) in triangles(before).zip(triangles(after)) {
+            let positions = new.map(|v| after.positions[v]);
+            assert_eq!(old.map(|v| before.positions[v]), positions);
+            let cell_ids = new.map(|v| after.cell_ids[v]);
+            assert_eq!(old.map(|v| before.cell_ids[v]), cell_ids);
+            let mut uvs = [Vec2::ZERO; 3];
+            projection.project_polygon(&positions, polygon_normal(&positions), &mut uvs);
+            assert_eq!(new.map(|v| after.uvs[v]), uvs);
+        }
+    }
+
+    #[test]
+    fn box_splits_at_seams() {
+        let before = sphere_mesh();
+        let mut after = before.clone();
+        let projection = UvProjection::Box { scale: 2.0 };
+        after.generate_uvs(projection);
+        assert!(after.positions.len() > before.positions.len());
+        assert_split_consistently(&before, &after, projection);
+        for t in triangles(&after) {
+            assert!(uv_area(&after, t) > 0.0);
+        }
+
+        // Projecting again doesn't split any further.
+        let mut again = after.clone();
+        again.generate_uvs(projection);
+        assert_eq!(again.positions.len(), after.positions.len());
+    }
+
+    #[test]
+    fn cylindrical_wraps_around_axis() {
+        let before = mesh(|p| sdf_primitives::capped_cylinder(p, 0.6, 0.5));
+        let mut after = before.clone();
+        let projection = UvProjection::Cylindrical {
+            center: Vec3A::ZERO,
+            axis: Vec3A::Y,
+            scale: 1.0,
+        };
+        after.generate_uvs(projection);
+        assert_split_consistently(&before, &after, projection);
+
+        let mut wrapped = 0;
+        for t in triangles(&after) {
+            let normal = polygon_normal(&t.map(|v| after.positions[v]));
+            let us = t.map(|v| after.uvs[v].x);
+            let spread = us.iter().fold(0.0f32, |s, u| s.max((u - us[0]).abs()));
+            wrapped += us.iter().any(|&u| u > 1.0) as usize;
+            // The caps are degenerate in this projection.
+            if normal.normalize().y.abs() < 0.5 {
+                assert!(spread < 0.1);
+                assert!(uv_area(&after, t) > 0.0);
+            }
+        }
+        assert!(wrapped > 0);
+    }
+
+    #[test]
+    fn spherical_latitude_and_orientation() {
+        let before = sphere_mesh();
+        let mut after = before.clone();
+        let projection = UvProjection::Spherical {
+            center: Vec3A::ZERO,
+            axis: Vec3A::Z,
+        };
+        after.generate_uvs(projection);
+        assert_split_consistently(&before, &after, projection);
+
+        for (p, uv) in after.positions.iter().zip(&after.uvs) {
+            let latitude = 1.0 - (p.z / p.length()).acos() / PI;
+            assert!((uv.y - latitude).abs() < 1e-5);
+        }
+        let mut wrapped = 0;
+        for t in triangles(&after) {
+            let us = t.map(|v| after.uvs[v].x);
+            wrapped += us.iter().any(|&u| u > 1.0) as usize;
+            // Triangles around the poles have no consistent angle.
+            let near_pole = t.map(|v| after.positions[v].truncate().length() < 0.1);
+            if !near_pole.contains(&true) {
+                assert!(uv_area(&after, t) > 0.0);
+            }
+        }
+        assert!(wrapped > 0);
+    }
+}
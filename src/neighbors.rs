@@ -0,0 +1,208 @@
+use crate::{CellId, CellOctree};
+
+impl CellOctree {
+    /// Find the cell sharing the face of `cell_id` on the `positive` (or
+    /// negative) side of `axis`.
+    ///
+    /// The neighbor is the same size as `cell_id`, or a coarser leaf if the
+    /// octree isn't subdivided that far. Returns `None` if the face is on the
+    /// boundary of the root cell or the neighbor is empty space.
+    pub fn face_neighbor(&self, cell_id: CellId, axis: usize, positive: bool) -> Option<CellId> {
+        let mut direction = [0; 3];
+        direction[axis] = if positive { 1 } else { -1 };
+        self.neighbor(cell_id, direction)
+    }
+
+    /// Find the cells sharing the edge of `cell_id` that is parallel to `axis`.
+    ///
+    /// `positive` selects the side of the cell for each of the other two axes,
+    /// in increasing order. Returns the face neighbors across each of those two
+    /// axes, followed by the diagonal neighbor across the edge. Like
+    /// [`Self::face_neighbor`], neighbors may be coarser than `cell_id`.
+    pub fn edge_neighbors(
+        &self,
+        cell_id: CellId,
+        axis: usize,
+        positive: [bool; 2],
+    ) -> [Option<CellId>; 3] {
+        let [a1, a2] = [(axis + 1) % 3, (axis + 2) % 3];
+        let [a1, a2] = [a1.min(a2), a1.max(a2)];
+        let [s1, s2] = positive.map(|p| if p { 1 } else { -1 });
+
+        let mut d1 = [0; 3];
+        d1[a1] = s1;
+        let mut d2 = [0; 3];
+        d2[a2] = s2;
+        let mut diagonal = d1;
+        diagonal[a2] = s2;
+
+        [
+            self.neighbor(cell_id, d1),
+            self.neighbor(cell_id, d2),
+            self.neighbor(cell_id, diagonal),
+        ]
+    }
+
+    /// Find the same-size or coarser cell that is offset from `cell_id` by one
+    /// cell width along each axis where `direction` is nonzero.
+    fn neighbor(&self, cell_id: CellId, direction: [i8; 3]) -> Option<CellId> {
+        let parent_id = self.all_cells[cell_id as usize].parent?;
+        let parent = &self.all_cells[parent_id as usize];
+        let octant = parent
+            .children
+            .iter()
+            .position(|&c| c == Some(cell_id))
+            .unwrap();
+
+        // Move to the mirrored octant along each direction, noting where we
+        // leave the parent.
+        let mut target_octant = octant;
+        let mut parent_direction = [0; 3];
+        for (axis, &d) in direction.iter().enumerate() {
+            let bit = 1 << axis;
+            let on_positive_side = octant & bit != 0;
+            if d != 0 {
+                target_octant ^= bit;
+                if on_positive_side == (d > 0) {
+                    parent_direction[axis] = d;
+                }
+            }
+        }
+
+        if parent_direction == [0; 3] {
+            // Sibling.
+            return parent.children[target_octant];
+        }
+
+        let container_id = self.neighbor(parent_id, parent_direction)?;
+        let container = &self.all_cells[container_id as usize];
+        if container.is_leaf {
+            Some(container_id)
+        } else {
+            container.children[target_octant]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives::{cube, sphere};
+    use glam::Vec3A;
+    use ilattice::extent::Extent;
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        // The cube reaches the root boundary, and the sphere is simplified
+        // less than its surroundings.
+        let sdf = |p: Vec3A| {
+            cube(Vec3A::new(2.0, 2.0, 0.3), p - Vec3A::new(0.0, 0.0, -0.8))
+                .min(sphere(0.4, p - Vec3A::splat(0.2)))
+        };
+        CellOctree::build(root, 5, 0.0001, 0.1, sdf).unwrap()
+    }
+
+    /// The same-size or coarser cell containing `p`, by descending from the
+    /// root.
+    fn cell_at(octree: &CellOctree, p: Vec3A, depth: u8) -> Option<CellId> {
+        let mut id = octree.root_id;
+        let root = &octree.all_cells[id as usize].extent;
+        if p.cmplt(root.minimum).any() || p.cmpge(root.least_upper_bound()).any() {
+            return None;
+        }
+        loop {
+            let cell = &octree.all_cells[id as usize];
+            if cell.is_leaf || cell.depth == depth {
+                return Some(id);
+            }
+            let center = cell.extent.center();
+            let octant = (p.x >= center.x) as usize
+                | ((p.y >= center.y) as usize) << 1
+                | ((p.z >= center.z) as usize) << 2;
+            id = cell.children[octant]?;
+        }
+    }
+
+    /// The cells that aren't below a leaf.
+    fn reachable_cells(octree: &CellOctree) -> Vec<CellId> {
+        let mut cells = Vec::new();
+        let mut stack = vec![octree.root_id];
+        while let Some(id) = stack.pop() {
+            cells.push(id);
+            let cell = &octree.all_cells[id as usize];
+            if !cell.is_leaf {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        cells
+    }
+
+    /// The point one cell width from the center of `cell_id` in `direction`.
+    fn across(octree: &CellOctree, cell_id: CellId, direction: [i8; 3]) -> Vec3A {
+        let cell = &octree.all_cells[cell_id as usize];
+        let direction = Vec3A::from(direction.map(f32::from));
+        cell.extent.center() + direction * cell.extent.shape
+    }
+
+    #[test]
+    fn parents_link_children() {
+        let octree = octree();
+        assert_eq!(octree.all_cells[octree.root_id as usize].parent, None);
+        for (id, cell) in octree.all_cells.iter().enumerate() {
+            for &child in cell.children.iter().flatten() {
+                assert_eq!(octree.all_cells[child as usize].parent, Some(id as CellId));
+            }
+        }
+    }
+
+    #[test]
+    fn face_neighbors_match_point_location() {
+        let octree = octree();
+        let (mut same_level, mut coarser, mut boundary) = (0, 0, 0);
+        for cell_id in reachable_cells(&octree) {
+            let depth = octree.all_cells[cell_id as usize].depth;
+            for axis in 0..3 {
+                for positive in [false, true] {
+                    let mut direction = [0; 3];
+                    direction[axis] = if positive { 1 } else { -1 };
+                    let p = across(&octree, cell_id, direction);
+                    let neighbor = octree.face_neighbor(cell_id, axis, positive);
+                    assert_eq!(neighbor, cell_at(&octree, p, depth));
+
+                    match neighbor {
+                        Some(n) if octree.all_cells[n as usize].depth == depth => same_level += 1,
+                        Some(_) => coarser += 1,
+                        // Outside of the root.
+                        None if cell_at(&octree, p, 0).is_none() => boundary += 1,
+                        None => {}
+                    }
+                }
+            }
+        }
+        assert!(same_level > 0 && coarser > 0 && boundary > 0);
+    }
+
+    #[test]
+    fn edge_neighbors_match_point_location() {
+        let octree = octree();
+        for cell_id in reachable_cells(&octree) {
+            let depth = octree.all_cells[cell_id as usize].depth;
+            for axis in 0..3 {
+                for positive in [[false, false], [false, true], [true, false], [true, true]] {
+                    let [a1, a2] = [(axis + 1) % 3, (axis + 2) % 3];
+                    let [a1, a2] = [a1.min(a2), a1.max(a2)];
+                    let [s1, s2] = positive.map(|p| if p { 1 } else { -1 });
+                    let (mut d1, mut d2) = ([0; 3], [0; 3]);
+                    d1[a1] = s1;
+                    d2[a2] = s2;
+                    let mut diagonal = d1;
+                    diagonal[a2] = s2;
+
+                    let expected = [d1, d2, diagonal]
+                        .map(|d| cell_at(&octree, across(&octree, cell_id, d), depth));
+                    assert_eq!(octree.edge_neighbors(cell_id, axis, positive), expected);
+                }
+            }
+        }
+    }
+}
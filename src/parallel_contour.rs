@@ -0,0 +1,196 @@
+use crate::{contour_octree::contour_work, Cell, CellId, CellOctree, ContourVisitor, ContourWork};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The number of independent work items to split the traversal into. This is
+/// independent of the number of threads so that the output is too, and large
+/// enough that threads with cheap items can take more of them.
+const MIN_WORK_ITEMS: usize = 256;
+
+/// Polygons produced by [`CellOctree::dual_contour_parallel`], in the same
+/// forms given to the callbacks of [`CellOctree::dual_contour`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContourPolygons {
+    pub quads: Vec<[CellId; 4]>,
+    pub triangles: Vec<[CellId; 3]>,
+}
+
+impl ContourPolygons {
+    fn append(&mut self, other: &mut Self) {
+        self.quads.append(&mut other.quads);
+        self.triangles.append(&mut other.triangles);
+    }
+}
+
+/// Collects the polygons and ignores the leaves.
+impl ContourVisitor for ContourPolygons {
+    fn visit_leaf(&mut self, _cell_id: CellId, _cell: &mut Cell) {}
+
+    fn visit_quad(&mut self, cells: [CellId; 4]) {
+        self.quads.push(cells);
+    }
+
+    fn visit_triangle(&mut self, cells: [CellId; 3]) {
+        self.triangles.push(cells);
+    }
+}
+
+impl CellOctree {
+    /// Like [`Self::dual_contour`], but splits the traversal into independent
+    /// subtrees, faces and edges near the root and processes them on
+    /// `num_threads` threads.
+    ///
+    /// `visit_leaf_cell` may be called concurrently, so it can't mutate the
+    /// cell. All leaves are visited before this returns the polygons, which
+    /// are in the same order for any number of threads, though not the same
+    /// order as [`Self::dual_contour`].
+    pub fn dual_contour_parallel(
+        &self,
+        num_threads: usize,
+        visit_leaf_cell: impl Fn(CellId, &Cell) + Sync,
+    ) -> ContourPolygons {
+        let mut polygons = ContourPolygons::default();
+        if self.all_cells.is_empty() {
+            return polygons;
+        }
+        let num_threads = num_threads.max(1);
+        let cells = &self.all_cells;
+
+        // Split the leaves into subtrees, breadth-first.
+        let mut subtrees = vec![self.root_id];
+        while subtrees.len() < MIN_WORK_ITEMS {
+            let mut next = Vec::with_capacity(8 * subtrees.len());
+            for cell_id in subtrees.drain(..) {
+                let cell = &cells[cell_id as usize];
+                if cell.is_leaf {
+                    visit_leaf_cell(cell_id, cell);
+                } else {
+                    next.extend(cell.children.iter().flatten());
+                }
+            }
+            subtrees = next;
+            if subtrees.is_empty() {
+                break;
+            }
+        }
+
+        // Likewise split the faces and edges, keeping the polygons that come
+        // out of the split.
+        let mut items = vec![ContourWork::Cell(self.root_id)];
+        while items.len() < MIN_WORK_ITEMS {
+            let mut next = Vec::with_capacity(26 * items.len());
+            for work in items.drain(..) {
+                contour_work(cells, &mut next, work, &mut polygons);
+            }
+            items = next;
+            if items.is_empty() {
+                break;
+            }
+        }
+
+        let next_subtree = AtomicUsize::new(0);
+        let next_item = AtomicUsize::new(0);
+        let item_polygons: Vec<_> = items
+            .iter()
+            .map(|_| Mutex::new(ContourPolygons::default()))
+            .collect();
+        std::thread::scope(|scope| {
+            for _ in 0..num_threads {
+                scope.spawn(|| {
+                    let mut stack = Vec::new();
+
+                    while let Some(&root) =
+                        subtrees.get(next_subtree.fetch_add(1, Ordering::Relaxed))
+                    {
+                        stack.push(root);
+                        while let Some(cell_id) = stack.pop() {
+                            let cell = &cells[cell_id as usize];
+                            if cell.is_leaf {
+                                visit_leaf_cell(cell_id, cell);
+                            } else {
+                                stack.extend(cell.children.iter().flatten());
+                            }
+                        }
+                    }
+
+                    let mut stack = Vec::new();
+                    loop {
+                        let i = next_item.fetch_add(1, Ordering::Relaxed);
+                        let Some(&work) = items.get(i) else { break };
+                        let mut out = ContourPolygons::default();
+                        stack.push(work);
+                        while let Some(work) = stack.pop() {
+                            contour_work(cells, &mut stack, work, &mut out);
+                        }
+                        *item_polygons[i].lock().unwrap() = out;
+                    }
+                });
+            }
+        });
+
+        // Merge in item order so the result doesn't depend on scheduling.
+        for out in item_polygons {
+            polygons.append(&mut out.into_inner().unwrap());
+        }
+        polygons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives;
+    use glam::Vec3A;
+    use ilattice::extent::Extent;
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let sdf = |p: Vec3A| {
+            sdf_primitives::cube(Vec3A::splat(0.5), p).min(sdf_primitives::sphere(0.4, p - 0.4))
+        };
+        CellOctree::build(root, 6, 0.01, 0.1, sdf).unwrap()
+    }
+
+    fn sorted(mut polygons: ContourPolygons) -> ContourPolygons {
+        polygons.quads.sort_unstable();
+        polygons.triangles.sort_unstable();
+        polygons
+    }
+
+    #[test]
+    fn matches_serial_contouring() {
+        let mut octree = octree();
+        let mut serial = ContourPolygons::default();
+        let mut serial_leaves = Vec::new();
+        octree.dual_contour(
+            |id, _| serial_leaves.push(id),
+            |q| serial.quads.push(q),
+            |t| serial.triangles.push(t),
+        );
+        serial_leaves.sort_unstable();
+        let serial = sorted(serial);
+
+        for num_threads in [1, 2, 7] {
+            let leaves = Mutex::new(Vec::new());
+            let polygons =
+                octree.dual_contour_parallel(num_threads, |id, _| leaves.lock().unwrap().push(id));
+            let mut leaves = leaves.into_inner().unwrap();
+            leaves.sort_unstable();
+            assert_eq!(leaves, serial_leaves);
+            assert_eq!(sorted(polygons), serial);
+        }
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let octree = octree();
+        let expected = octree.dual_contour_parallel(1, |_, _| {});
+        assert!(!expected.quads.is_empty());
+        for num_threads in [1, 2, 4, 7, 16] {
+            for _ in 0..3 {
+                let polygons = octree.dual_contour_parallel(num_threads, |_, _| {});
+                assert_eq!(polygons, expected);
+            }
+        }
+    }
+}
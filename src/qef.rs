@@ -37,6 +37,28 @@ impl Qef {
         }
     }
 
+    /// All coefficients: the upper triangle of `A`, then `b`, then `c`.
+    pub fn to_array(&self) -> [f32; 10] {
+        [
+            self.a00, self.a01, self.a02, self.a11, self.a12, self.a22, self.b.x, self.b.y,
+            self.b.z, self.c,
+        ]
+    }
+
+    /// Inverse of [`Self::to_array`].
+    pub fn from_array([a00, a01, a02, a11, a12, a22, bx, by, bz, c]: [f32; 10]) -> Self {
+        Self {
+            a00,
+            a01,
+            a02,
+            a11,
+            a12,
+            a22,
+            b: Vec3A::new(bx, by, bz),
+            c,
+        }
+    }
+
     /// Residual L2 error. `x^T A x - 2 b^T x + c`
     pub fn error(&self, p: Vec3A) -> f32 {
         // Ax
@@ -71,6 +93,44 @@ impl Qef {
         denom * Vec3A::new(nom0, nom1, nom2)
     }
 
+    /// Eigenvalues of the symmetric matrix `A`, in decreasing order.
+    ///
+    /// For a sum of plane quadrics, `A` is the sum of the normals' outer
+    /// products, so the number of large eigenvalues is the number of
+    /// independent normal directions.
+    pub fn eigenvalues(&self) -> [f32; 3] {
+        // The trigonometric solution loses half the precision near repeated
+        // eigenvalues, so work in f64.
+        let [a00, a01, a02, a11, a12, a22] =
+            [self.a00, self.a01, self.a02, self.a11, self.a12, self.a22].map(f64::from);
+
+        let p1 = a01 * a01 + a02 * a02 + a12 * a12;
+        if p1 == 0.0 {
+            // Already diagonal.
+            let mut diag = [a00, a11, a22].map(|x| x as f32);
+            diag.sort_by(|a, b| b.total_cmp(a));
+            return diag;
+        }
+
+        // Closed form for symmetric 3x3 matrices (Smith, 1961).
+        let q = (a00 + a11 + a22) / 3.0;
+        let (d00, d11, d22) = (a00 - q, a11 - q, a22 - q);
+        let p2 = d00 * d00 + d11 * d11 + d22 * d22 + 2.0 * p1;
+        let p = (p2 / 6.0).sqrt();
+
+        // B = (A - qI) / p
+        let [b00, b01, b02, b11, b12, b22] = [d00, a01, a02, d11, a12, d22].map(|x| x / p);
+        let det_b = b00 * (b11 * b22 - b12 * b12) - b01 * (b01 * b22 - b12 * b02)
+            + b02 * (b01 * b12 - b11 * b02);
+        let r = (0.5 * det_b).clamp(-1.0, 1.0);
+        let phi = r.acos() / 3.0;
+
+        let e0 = q + 2.0 * p * phi.cos();
+        let e2 = q + 2.0 * p * (phi + 2.0 * std::f64::consts::FRAC_PI_3).cos();
+        let e1 = 3.0 * q - e0 - e2;
+        [e0, e1, e2].map(|x| x as f32)
+    }
+
     pub fn plane(p: Vec3A, n: Vec3A) -> Self {
         let d = p.dot(n);
         Self::from_coefficients(self_outer_product(n.into()), d * n, d * d)
@@ -122,3 +182,49 @@ fn self_outer_product([a, b, c]: [f32; 3]) -> [[f32; 3]; 3] {
         [a * c, b * c, c * c],
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Mat3, Quat};
+
+    fn qef_with_matrix(a: Mat3) -> Qef {
+        Qef::from_coefficients(a.to_cols_array_2d(), Vec3A::ZERO, 0.0)
+    }
+
+    fn assert_eigenvalues(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.into_iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn eigenvalues_of_diagonal_matrix() {
+        let a = Mat3::from_diagonal([2.0, 5.0, 3.0].into());
+        assert_eigenvalues(qef_with_matrix(a).eigenvalues(), [5.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn eigenvalues_of_rotated_matrix() {
+        let r = Mat3::from_quat(Quat::from_euler(glam::EulerRot::XYZ, 0.3, -1.1, 0.7));
+        let a = r * Mat3::from_diagonal([2.0, 5.0, 3.0].into()) * r.transpose();
+        assert_eigenvalues(qef_with_matrix(a).eigenvalues(), [5.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn eigenvalues_with_repeated_values() {
+        let r = Mat3::from_quat(Quat::from_euler(glam::EulerRot::XYZ, 0.5, 0.2, -0.9));
+
+        // A single plane: one nonzero eigenvalue, repeated zeros.
+        let plane = Qef::plane(Vec3A::ZERO, Vec3A::from(r.x_axis));
+        assert_eigenvalues(plane.eigenvalues(), [1.0, 0.0, 0.0]);
+
+        // Repeated largest eigenvalue.
+        let a = r * Mat3::from_diagonal([4.0, 4.0, 1.0].into()) * r.transpose();
+        assert_eigenvalues(qef_with_matrix(a).eigenvalues(), [4.0, 4.0, 1.0]);
+
+        // A multiple of the identity.
+        let a = r * Mat3::from_diagonal([2.0; 3].into()) * r.transpose();
+        assert_eigenvalues(qef_with_matrix(a).eigenvalues(), [2.0, 2.0, 2.0]);
+    }
+}
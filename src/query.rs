@@ -0,0 +1,235 @@
+use crate::{cell_octree::distance_to_extent, CellId, CellOctree};
+use glam::Vec3A;
+use ilattice::extent::Extent;
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+impl CellOctree {
+    /// Find the leaf (or pseudo-leaf) containing `p`.
+    ///
+    /// Returns `None` if `p` is outside of the root cell or in empty space.
+    pub fn find_leaf(&self, p: Vec3A) -> Option<CellId> {
+        let mut cell_id = self.root_id;
+        let mut cell = self.all_cells.get(cell_id as usize)?;
+        if !extent_contains(&cell.extent, p) {
+            return None;
+        }
+        while !cell.is_leaf {
+            let center = cell.extent.center();
+            let octant = (p.x >= center.x) as usize
+                | ((p.y >= center.y) as usize) << 1
+                | ((p.z >= center.z) as usize) << 2;
+            cell_id = cell.children[octant]?;
+            cell = &self.all_cells[cell_id as usize];
+        }
+        Some(cell_id)
+    }
+
+    /// Find all leaves (and pseudo-leaves) whose extents intersect `extent`,
+    /// including those that only touch its boundary.
+    pub fn cells_in_extent(&self, extent: &Extent<Vec3A>) -> Vec<CellId> {
+        let mut found = Vec::new();
+        if self.all_cells.is_empty() {
+            return found;
+        }
+        let mut stack = vec![self.root_id];
+        while let Some(cell_id) = stack.pop() {
+            let cell = &self.all_cells[cell_id as usize];
+            if !extents_intersect(&cell.extent, extent) {
+                continue;
+            }
+            if cell.is_leaf {
+                found.push(cell_id);
+            } else {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        found
+    }
+
+    /// Find the leaf whose vertex is nearest to `p`, and that vertex.
+    pub fn nearest_vertex(&self, p: Vec3A) -> Option<(CellId, Vec3A)> {
+        if self.all_cells.is_empty() {
+            return None;
+        }
+
+        // Best-first search. Vertices may lie outside of their cells, so the
+        // lower bound for each cell accounts for the farthest escape.
+        let lower_bound = |cell_id: CellId| {
+            let extent = &self.all_cells[cell_id as usize].extent;
+            (distance_to_extent(extent, p) - self.vertex_escape).max(0.0)
+        };
+        let mut best: Option<(CellId, Vec3A)> = None;
+        let mut best_distance = f32::INFINITY;
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: lower_bound(self.root_id),
+            cell_id: self.root_id,
+        });
+        while let Some(Candidate { distance, cell_id }) = queue.pop() {
+            if distance >= best_distance {
+                break;
+            }
+            let cell = &self.all_cells[cell_id as usize];
+            if cell.is_leaf {
+                let vertex = Vec3A::from(cell.vertex_estimate);
+                let vertex_distance = vertex.distance(p);
+                if vertex_distance < best_distance {
+                    best_distance = vertex_distance;
+                    best = Some((cell_id, vertex));
+                }
+            } else {
+                for &child in cell.children.iter().flatten() {
+                    queue.push(Candidate {
+                        distance: lower_bound(child),
+                        cell_id: child,
+                    });
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Ordered so that the nearest candidate is popped first from a max-heap.
+struct Candidate {
+    distance: f32,
+    cell_id: CellId,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+fn extent_contains(extent: &Extent<Vec3A>, p: Vec3A) -> bool {
+    let lub = extent.minimum + extent.shape;
+    p.cmpge(extent.minimum).all() && p.cmple(lub).all()
+}
+
+fn extents_intersect(a: &Extent<Vec3A>, b: &Extent<Vec3A>) -> bool {
+    let a_lub = a.minimum + a.shape;
+    let b_lub = b.minimum + b.shape;
+    a.minimum.cmple(b_lub).all() && b.minimum.cmple(a_lub).all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_primitives::sphere;
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        CellOctree::build(root, 5, 0.0001, 0.1, |p| sphere(0.6, p)).unwrap()
+    }
+
+    fn leaves(octree: &CellOctree) -> Vec<CellId> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![octree.root_id];
+        while let Some(id) = stack.pop() {
+            let cell = &octree.all_cells[id as usize];
+            if cell.is_leaf {
+                leaves.push(id);
+            } else {
+                stack.extend(cell.children.iter().flatten());
+            }
+        }
+        leaves.sort();
+        leaves
+    }
+
+    /// The nearest leaf vertex by brute force.
+    fn brute_force_nearest(octree: &CellOctree, p: Vec3A) -> f32 {
+        leaves(octree)
+            .into_iter()
+            .map(|id| Vec3A::from(octree.all_cells[id as usize].vertex_estimate).distance(p))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn find_leaf_contains_point() {
+        let octree = octree();
+        for &id in &leaves(&octree) {
+            let cell = &octree.all_cells[id as usize];
+            assert_eq!(octree.find_leaf(cell.extent.center()), Some(id));
+        }
+        // Empty space inside and outside of the sphere.
+        assert_eq!(octree.find_leaf(Vec3A::ZERO), None);
+        assert_eq!(octree.find_leaf(Vec3A::splat(0.9)), None);
+        // Outside of the root.
+        assert_eq!(octree.find_leaf(Vec3A::new(0.6, 0.0, 1.5)), None);
+    }
+
+    #[test]
+    fn cells_in_extent_matches_brute_force() {
+        let octree = octree();
+        let extent = Extent::from_min_and_shape(Vec3A::new(0.1, -0.3, 0.2), Vec3A::splat(0.5));
+        let mut found = octree.cells_in_extent(&extent);
+        found.sort();
+        let expected: Vec<_> = leaves(&octree)
+            .into_iter()
+            .filter(|&id| extents_intersect(&octree.all_cells[id as usize].extent, &extent))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+
+        let outside = Extent::from_min_and_shape(Vec3A::splat(2.0), Vec3A::ONE);
+        assert!(octree.cells_in_extent(&outside).is_empty());
+    }
+
+    #[test]
+    fn nearest_vertex_matches_brute_force() {
+        let octree = octree();
+        for p in [
+            Vec3A::ZERO,
+            Vec3A::new(0.6, 0.0, 0.0),
+            Vec3A::new(-0.3, 0.5, 0.1),
+            Vec3A::splat(3.0),
+        ] {
+            let (id, vertex) = octree.nearest_vertex(p).unwrap();
+            assert_eq!(
+                vertex,
+                Vec3A::from(octree.all_cells[id as usize].vertex_estimate)
+            );
+            assert_eq!(vertex.distance(p), brute_force_nearest(&octree, p));
+        }
+    }
+
+    #[test]
+    fn nearest_vertex_sees_moved_vertices() {
+        let mut octree = octree();
+        // Move the top vertex of the sphere below the bottom.
+        let moved = leaves(&octree)
+            .into_iter()
+            .max_by(|&a, &b| {
+                let z = |id: CellId| octree.all_cells[id as usize].vertex_estimate.z;
+                z(a).total_cmp(&z(b))
+            })
+            .unwrap();
+        let target = Vec3A::new(0.0, 0.0, -3.0);
+        octree.dual_contour(
+            |cell_id, cell| {
+                if cell_id == moved {
+                    cell.vertex_estimate = target.into();
+                }
+            },
+            |_| {},
+            |_| {},
+        );
+        assert_eq!(octree.nearest_vertex(target), Some((moved, target)));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{central_gradient, CellId, CellOctree};
+use glam::Vec3A;
+use ilattice::extent::Extent;
+
+/// Maximum number of sphere tracing steps within one leaf, so rays that graze
+/// the surface don't stall.
+const MAX_STEPS_PER_LEAF: u32 = 64;
+/// Distance to the surface, relative to the leaf size, at which the ray
+/// counts as hitting it.
+const HIT_TOLERANCE: f32 = 1e-4;
+/// Step size of the central differences for hit normals, relative to the
+/// leaf size. Much larger than [`HIT_TOLERANCE`] so rounding doesn't make
+/// the normals noisy.
+const NORMAL_DELTA: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// Ray parameter of the hit, in units of the ray direction's length.
+    pub t: f32,
+    pub position: Vec3A,
+    /// Unit normal of the surface, from the gradient of the SDF.
+    pub normal: Vec3A,
+    /// The leaf containing the hit.
+    pub cell_id: CellId,
+}
+
+impl CellOctree {
+    /// Find the first surface crossing on the ray `origin + t * dir` for `t`
+    /// in `[0, max_t]`.
+    ///
+    /// Leaves are visited front-to-back, skipping empty space, and the ray is
+    /// sphere traced through each leaf with `sdf`, the same SDF that the
+    /// octree was built with, capped by [`Self::surface_sdf`]. So hits land on
+    /// the surface of the SDF, which the contoured mesh approximates. `sdf`
+    /// must not overestimate the distance to its surface.
+    pub fn raycast(
+        &self,
+        origin: Vec3A,
+        dir: Vec3A,
+        max_t: f32,
+        sdf: impl Fn(Vec3A) -> f32,
+    ) -> Option<RayHit> {
+        let root = self.all_cells.get(self.root_id as usize)?;
+        let inv_dir = dir.recip();
+        let dir_length = dir.length();
+        if dir_length == 0.0 {
+            return None;
+        }
+        let sdf = self.surface_sdf(sdf);
+        let sdf = |p| sdf(p) - self.iso_value;
+
+        // Sphere tracing never steps past the surface, so each leaf can
+        // continue from where the last one stopped.
+        let mut t = 0.0f32;
+
+        let mut stack = Vec::new();
+        if let Some(span) = ray_extent_span(origin, inv_dir, max_t, &root.extent) {
+            stack.push((self.root_id, span));
+        }
+        let mut children = Vec::with_capacity(8);
+        while let Some((cell_id, (t0, t1))) = stack.pop() {
+            let cell = &self.all_cells[cell_id as usize];
+            if !cell.is_leaf {
+                children.clear();
+                for &child_id in cell.children.iter().flatten() {
+                    let child = &self.all_cells[child_id as usize];
+                    if let Some(span) = ray_extent_span(origin, inv_dir, max_t, &child.extent) {
+                        children.push((child_id, span));
+                    }
+                }
+                // Push the farthest child first so the nearest is visited
+                // first.
+                children.sort_by(|a, b| b.1 .0.total_cmp(&a.1 .0));
+                stack.extend_from_slice(&children);
+                continue;
+            }
+
+            let leaf_size = cell.extent.shape.max_element();
+            let tolerance = HIT_TOLERANCE * leaf_size;
+            t = t.max(t0);
+            for _ in 0..MAX_STEPS_PER_LEAF {
+                if t > t1 {
+                    break;
+                }
+                let position = origin + t * dir;
+                let distance = sdf(position).abs();
+                if distance <= tolerance {
+                    let normal = central_gradient(sdf, position, NORMAL_DELTA * leaf_size)
+                        .normalize_or_zero();
+                    return Some(RayHit {
+                        t,
+                        position,
+                        normal,
+                        cell_id,
+                    });
+                }
+                t += distance / dir_length;
+            }
+        }
+
+        None
+    }
+}
+
+/// The `[t_enter, t_exit]` interval where the ray overlaps `extent`, clipped
+/// to `[0, max_t]`.
+fn ray_extent_span(
+    origin: Vec3A,
+    inv_dir: Vec3A,
+    max_t: f32,
+    extent: &Extent<Vec3A>,
+) -> Option<(f32, f32)> {
+    let ta = (extent.minimum - origin) * inv_dir;
+    let tb = (extent.minimum + extent.shape - origin) * inv_dir;
+    let t_enter = ta.min(tb).max_element().max(0.0);
+    let t_exit = ta.max(tb).min_element().min(max_t);
+    (t_enter <= t_exit).then_some((t_enter, t_exit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives::sphere, MeshOptions};
+
+    fn sdf(p: Vec3A) -> f32 {
+        sphere(0.6, p)
+    }
+
+    fn octree() -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        CellOctree::build(root, 5, 0.0001, 0.1, sdf).unwrap()
+    }
+
+    /// The nearest intersection of the ray with the triangles of the
+    /// contoured mesh.
+    fn mesh_intersection(octree: &mut CellOctree, origin: Vec3A, dir: Vec3A) -> Option<f32> {
+        let mesh = octree.contour_to_mesh(&MeshOptions::default(), sdf);
+        mesh.tri_indices
+            .chunks_exact(3)
+            .filter_map(|tri| {
+                let [p0, p1, p2] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+                let (e1, e2) = (p1 - p0, p2 - p0);
+                let h = dir.cross(e2);
+                let det = e1.dot(h);
+                if det.abs() < 1e-12 {
+                    return None;
+                }
+                let s = origin - p0;
+                let u = s.dot(h) / det;
+                let q = s.cross(e1);
+                let v = dir.dot(q) / det;
+                let t = e2.dot(q) / det;
+                (u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t >= 0.0).then_some(t)
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn hits_sphere_near_mesh() {
+        let mut octree = octree();
+        let leaf_size = 2.0 / 32.0;
+        for (origin, dir) in [
+            (Vec3A::new(-2.0, 0.1, 0.2), Vec3A::X),
+            (Vec3A::new(0.3, 2.0, -0.1), Vec3A::new(0.0, -2.0, 0.0)),
+            (Vec3A::splat(1.5), Vec3A::splat(-1.0)),
+            // From inside the sphere.
+            (Vec3A::new(0.1, 0.0, 0.0), Vec3A::new(0.2, 0.3, -1.0)),
+        ] {
+            let hit = octree.raycast(origin, dir, 10.0, sdf).unwrap();
+            assert!((hit.position - (origin + hit.t * dir)).length() < 1e-5);
+            assert!(sdf(hit.position).abs() < 1e-3);
+            assert!((hit.normal - hit.position.normalize()).length() < 1e-3);
+            let leaf = &octree.all_cells[hit.cell_id as usize];
+            assert!(leaf.is_leaf);
+
+            let mesh_t = mesh_intersection(&mut octree, origin, dir).unwrap();
+            assert!(
+                (hit.t - mesh_t).abs() * dir.length() < 0.5 * leaf_size,
+                "{} != {mesh_t}",
+                hit.t
+            );
+        }
+    }
+
+    #[test]
+    fn misses_return_none() {
+        let octree = octree();
+        // Passes beside the sphere.
+        let origin = Vec3A::new(-2.0, 0.7, 0.0);
+        assert_eq!(octree.raycast(origin, Vec3A::X, 10.0, sdf), None);
+        // Points away from it.
+        let origin = Vec3A::new(-2.0, 0.0, 0.0);
+        assert_eq!(octree.raycast(origin, Vec3A::NEG_X, 10.0, sdf), None);
+    }
+
+    #[test]
+    fn respects_max_t() {
+        let octree = octree();
+        let origin = Vec3A::new(-2.0, 0.0, 0.0);
+        let dir = Vec3A::new(0.5, 0.0, 0.0);
+        let hit = octree.raycast(origin, dir, 10.0, sdf).unwrap();
+        assert!((hit.t - 2.8).abs() < 1e-3);
+        assert_eq!(octree.raycast(origin, dir, 2.7, sdf), None);
+        assert_eq!(octree.raycast(origin, dir, 2.81, sdf), Some(hit));
+    }
+}
@@ -31,6 +31,15 @@ pub fn cell_is_bipolar(samples: &[f32; 8]) -> bool {
     any_negative && any_positive
 }
 
+/// The SDF of `extent` shrunk by `inset` on each side, which is positive
+/// everywhere on the boundary of `extent`. See
+/// [`crate::BuildOptions::cap_to_root`].
+pub(crate) fn root_cap(extent: &Extent<Vec3A>, inset: f32) -> impl Fn(Vec3A) -> f32 {
+    let half_shape = 0.5 * extent.shape - Vec3A::splat(inset);
+    let center = extent.minimum + 0.5 * extent.shape;
+    move |p| crate::sdf_primitives::cube(half_shape, p - center)
+}
+
 pub fn central_gradient(sdf: impl Fn(Vec3A) -> f32, p: Vec3A, delta: f32) -> Vec3A {
     let h = 0.5 * delta;
     let dx = Vec3A::new(h, 0.0, 0.0);
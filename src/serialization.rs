@@ -0,0 +1,493 @@
+//! Binary format for [`CellOctree`].
+//!
+//! All values are little-endian:
+//!
+//! ```text
+//! magic     [u8; 4] = "ODCO"
+//! version   u16
+//! flags     u16      (bit 0: QEFs are stored, bit 1: materials are stored,
+//!                     bit 2: capped to the root; bits 1 and 2 since version 3)
+//! iso_value f32      (since version 2)
+//! cap_inset f32      (if capped)
+//! root_id   u32
+//! num_cells u32
+//! cells     [Cell; num_cells]
+//! qefs      [[f32; 20]; num_cells] (if stored)
+//! materials [[u8; 8]; num_cells]   (if stored)
+//! checksum  u32      (CRC-32 of everything above)
+//! ```
+//!
+//! Each cell is stored as its extent minimum and shape, 8 samples, 8 child IDs
+//! (`u32::MAX` for none), vertex estimate, QEF error, and then one byte each
+//! for the feature, depth, and leaf flag. Parent links are restored from the
+//! children.
+
+use crate::{Cell, CellId, CellOctree, CellQefs, Qef, SharpFeature};
+use glam::{Vec3, Vec3A};
+use ilattice::extent::Extent;
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = *b"ODCO";
+const VERSION: u16 = 3;
+const FLAG_QEFS: u16 = 1;
+const FLAG_MATERIALS: u16 = 2;
+const FLAG_CAPPED: u16 = 4;
+/// The flags that each version can have, indexed by version.
+const VERSION_FLAGS: [u16; VERSION as usize + 1] = [
+    0,
+    FLAG_QEFS,
+    FLAG_QEFS,
+    FLAG_QEFS | FLAG_MATERIALS | FLAG_CAPPED,
+];
+const NULL_CHILD: u32 = u32::MAX;
+const HEADER_SIZE: usize = 20;
+/// Magic, version and flags.
+const HEADER_PREFIX_SIZE: usize = 8;
+const CELL_SIZE: usize = 4 * (3 + 3 + 8 + 8 + 3 + 1) + 3;
+const QEFS_SIZE: usize = 4 * 20;
+const MATERIALS_SIZE: usize = 8;
+
+impl CellOctree {
+    /// Write the octree in a compact, versioned binary format.
+    ///
+    /// Stored QEFs and corner materials are included, if any. Traversal
+    /// stacks are not.
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        let num_cells = u32::try_from(self.all_cells.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many cells"))?;
+        let has_qefs = !self.qefs.is_empty();
+        let has_materials = !self.corner_materials.is_empty();
+
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE
+                + self.all_cells.len()
+                    * (CELL_SIZE
+                        + has_qefs as usize * QEFS_SIZE
+                        + has_materials as usize * MATERIALS_SIZE)
+                + 4,
+        );
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        let mut flags = 0;
+        if has_qefs {
+            flags |= FLAG_QEFS;
+        }
+        if has_materials {
+            flags |= FLAG_MATERIALS;
+        }
+        if self.cap_inset.is_some() {
+            flags |= FLAG_CAPPED;
+        }
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&self.iso_value.to_le_bytes());
+        if let Some(inset) = self.cap_inset {
+            bytes.extend_from_slice(&inset.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.root_id.to_le_bytes());
+        bytes.extend_from_slice(&num_cells.to_le_bytes());
+
+        let put_f32s = |bytes: &mut Vec<u8>, xs: &[f32]| {
+            for x in xs {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        };
+        for cell in &self.all_cells {
+            put_f32s(&mut bytes, &cell.extent.minimum.to_array());
+            put_f32s(&mut bytes, &cell.extent.shape.to_array());
+            put_f32s(&mut bytes, &cell.samples);
+            for child in cell.children {
+                bytes.extend_from_slice(&child.unwrap_or(NULL_CHILD).to_le_bytes());
+            }
+            put_f32s(&mut bytes, &cell.vertex_estimate.to_array());
+            put_f32s(&mut bytes, &[cell.qef_error]);
+            bytes.extend_from_slice(&[
+                feature_to_byte(cell.feature),
+                cell.depth,
+                cell.is_leaf as u8,
+            ]);
+        }
+        for qefs in &self.qefs {
+            put_f32s(&mut bytes, &qefs.regularized.to_array());
+            put_f32s(&mut bytes, &qefs.exact.to_array());
+        }
+        for materials in &self.corner_materials {
+            bytes.extend_from_slice(materials);
+        }
+
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+
+        writer.write_all(&bytes)
+    }
+
+    /// Read an octree written by [`Self::save`].
+    ///
+    /// Returns an [`io::ErrorKind::InvalidData`] error if the data is
+    /// corrupted or from an unsupported version.
+    pub fn load(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < HEADER_PREFIX_SIZE + 4 {
+            return Err(invalid_data("octree data is truncated"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(invalid_data("octree checksum mismatch"));
+        }
+
+        let mut reader = ByteReader { bytes: body };
+        if reader.take::<4>() != MAGIC {
+            return Err(invalid_data("not an octree file"));
+        }
+        let version = u16::from_le_bytes(reader.take());
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported octree version {version}"
+            )));
+        }
+        let flags = u16::from_le_bytes(reader.take());
+        if flags & !VERSION_FLAGS[version as usize] != 0 {
+            return Err(invalid_data(format!(
+                "invalid flags {flags:#x} for octree version {version}"
+            )));
+        }
+        let has_qefs = flags & FLAG_QEFS != 0;
+        let has_materials = flags & FLAG_MATERIALS != 0;
+        let is_capped = flags & FLAG_CAPPED != 0;
+        // Version 1 has no iso value.
+        let mut header_size = if version == 1 { HEADER_SIZE - 4 } else { HEADER_SIZE };
+        header_size += is_capped as usize * 4;
+        if body.len() < header_size {
+            return Err(invalid_data("octree data is truncated"));
+        }
+        let [iso_value] = if version == 1 { [0.0] } else { reader.f32s() };
+        let cap_inset = is_capped.then(|| reader.f32s::<1>()[0]);
+        let root_id = reader.u32();
+        let num_cells = reader.u32() as usize;
+
+        let expected_len = num_cells
+            * (CELL_SIZE
+                + has_qefs as usize * QEFS_SIZE
+                + has_materials as usize * MATERIALS_SIZE);
+        if reader.bytes.len() != expected_len {
+            return Err(invalid_data("octree data has the wrong length"));
+        }
+        if root_id as usize >= num_cells {
+            return Err(invalid_data("octree root ID is out of bounds"));
+        }
+
+        let mut all_cells = Vec::with_capacity(num_cells);
+        for _ in 0..num_cells {
+            let minimum = Vec3A::from(reader.f32s::<3>());
+            let shape = Vec3A::from(reader.f32s::<3>());
+            let samples = reader.f32s::<8>();
+            let children = [(); 8].map(|_| match reader.u32() {
+                NULL_CHILD => None,
+                id => Some(id),
+            });
+            if children.iter().flatten().any(|&c| c as usize >= num_cells) {
+                return Err(invalid_data("octree child ID is out of bounds"));
+            }
+            let vertex_estimate = Vec3::from(reader.f32s::<3>());
+            let [qef_error] = reader.f32s::<1>();
+            let [feature, depth, is_leaf] = reader.take::<3>();
+            let feature = feature_from_byte(feature)
+                .ok_or_else(|| invalid_data("invalid sharp feature"))?;
+            let is_leaf = match is_leaf {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid_data("invalid leaf flag")),
+            };
+            all_cells.push(Cell {
+                extent: Extent::from_min_and_shape(minimum, shape),
+                samples,
+                children,
+                parent: None,
+                vertex_estimate,
+                qef_error,
+                feature,
+                depth,
+                is_leaf,
+            });
+        }
+
+        let qefs = if has_qefs {
+            (0..num_cells)
+                .map(|_| CellQefs {
+                    regularized: Qef::from_array(reader.f32s()),
+                    exact: Qef::from_array(reader.f32s()),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let corner_materials = if has_materials {
+            (0..num_cells).map(|_| reader.take()).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut octree = Self::new(root_id as CellId, all_cells, qefs);
+        octree.corner_materials = corner_materials;
+        octree.iso_value = iso_value;
+        octree.cap_inset = cap_inset;
+        Ok(octree)
+    }
+}
+
+/// Reads from a slice whose length has already been validated.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl ByteReader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, tail) = self.bytes.split_at(N);
+        self.bytes = tail;
+        head.try_into().unwrap()
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn f32s<const N: usize>(&mut self) -> [f32; N] {
+        [(); N].map(|_| f32::from_le_bytes(self.take()))
+    }
+}
+
+fn feature_to_byte(feature: SharpFeature) -> u8 {
+    match feature {
+        SharpFeature::Smooth => 0,
+        SharpFeature::Edge => 1,
+        SharpFeature::Corner => 2,
+    }
+}
+
+fn feature_from_byte(byte: u8) -> Option<SharpFeature> {
+    match byte {
+        0 => Some(SharpFeature::Smooth),
+        1 => Some(SharpFeature::Edge),
+        2 => Some(SharpFeature::Corner),
+        _ => None,
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// CRC-32 (IEEE 802.3).
+fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    !bytes.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdf_primitives, BuildOptions, MeshOptions};
+
+    fn sdf(p: Vec3A) -> f32 {
+        sdf_primitives::cube(Vec3A::splat(0.5), p).min(sdf_primitives::sphere(0.4, p - 0.4))
+    }
+
+    fn octree(store_qefs: bool) -> CellOctree {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let options = BuildOptions {
+            max_depth: 5,
+            error_tolerance: 0.001,
+            store_qefs,
+            ..Default::default()
+        };
+        CellOctree::build_with_options(root, &options, sdf).unwrap()
+    }
+
+    fn save(octree: &CellOctree) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        octree.save(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Replace the checksum after editing `bytes`.
+    fn resign(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 4);
+        let checksum = crc32(bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip_contours_identically() {
+        for store_qefs in [false, true] {
+            let mut original = octree(store_qefs);
+            let mut loaded = CellOctree::load(save(&original).as_slice()).unwrap();
+
+            assert_eq!(loaded.root_id(), original.root_id());
+            assert_eq!(loaded.all_cells().len(), original.all_cells().len());
+            assert_eq!(loaded.qefs(), original.qefs());
+            assert!(loaded.build_report().is_none());
+
+            let options = MeshOptions::default();
+            let expected = original.contour_to_mesh(&options, sdf);
+            let actual = loaded.contour_to_mesh(&options, sdf);
+            assert_eq!(actual.positions, expected.positions);
+            assert_eq!(actual.normals, expected.normals);
+            assert_eq!(actual.cell_ids, expected.cell_ids);
+            assert_eq!(actual.tri_indices, expected.tri_indices);
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_cap() {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(1.5));
+        let options = BuildOptions {
+            max_depth: 4,
+            cap_to_root: true,
+            ..Default::default()
+        };
+        let mut original = CellOctree::build_with_options(root, &options, sdf).unwrap();
+        let mut loaded = CellOctree::load(save(&original).as_slice()).unwrap();
+        assert_eq!(loaded.cap_inset, original.cap_inset);
+
+        let options = MeshOptions::default();
+        let expected = original.contour_to_mesh(&options, sdf);
+        let actual = loaded.contour_to_mesh(&options, sdf);
+        assert_eq!(actual.positions, expected.positions);
+        assert_eq!(actual.normals, expected.normals);
+    }
+
+    #[test]
+    fn round_trip_keeps_materials() {
+        let root = Extent::from_min_and_shape(Vec3A::splat(-1.0), Vec3A::splat(2.0));
+        let options = BuildOptions {
+            max_depth: 4,
+            ..Default::default()
+        };
+        let left = |p: Vec3A| sdf(p).max(p.x);
+        let right = |p: Vec3A| sdf(p).max(-p.x);
+        let materials: [&dyn Fn(Vec3A) -> f32; 2] = [&left, &right];
+        let original = CellOctree::build_multi_material(root, &options, &materials).unwrap();
+        let loaded = CellOctree::load(save(&original).as_slice()).unwrap();
+        assert_eq!(loaded.corner_materials(), original.corner_materials());
+
+        let options = MeshOptions::default();
+        let expected = original.contour_materials(&options, &materials);
+        let actual = loaded.contour_materials(&options, &materials);
+        assert_eq!(actual.mesh.positions, expected.mesh.positions);
+        assert_eq!(actual.mesh.tri_indices, expected.mesh.tri_indices);
+        assert_eq!(actual.tri_materials, expected.tri_materials);
+    }
+
+    #[test]
+    fn reads_older_versions() {
+        let mut original = octree(true);
+        let bytes = save(&original);
+        let options = MeshOptions::default();
+        let expected = original.contour_to_mesh(&options, sdf);
+
+        let mut v2 = bytes.clone();
+        v2[4..6].copy_from_slice(&2u16.to_le_bytes());
+        resign(&mut v2);
+        // Version 1 has no iso value.
+        let mut v1 = bytes;
+        v1[4..6].copy_from_slice(&1u16.to_le_bytes());
+        v1.drain(HEADER_PREFIX_SIZE..HEADER_PREFIX_SIZE + 4);
+        resign(&mut v1);
+
+        for bytes in [v1, v2] {
+            let mut loaded = CellOctree::load(bytes.as_slice()).unwrap();
+            assert_eq!(loaded.qefs(), original.qefs());
+            let actual = loaded.contour_to_mesh(&options, sdf);
+            assert_eq!(actual.positions, expected.positions);
+            assert_eq!(actual.tri_indices, expected.tri_indices);
+        }
+    }
+
+    #[test]
+    fn rejects_flags_unknown_to_version() {
+        for (version, flag) in [(2, FLAG_MATERIALS), (2, FLAG_CAPPED), (VERSION, 8)] {
+            let mut bytes = save(&octree(false));
+            bytes[4..6].copy_from_slice(&u16::to_le_bytes(version));
+            bytes[6..8].copy_from_slice(&flag.to_le_bytes());
+            resign(&mut bytes);
+            let error = CellOctree::load(bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn checksum_catches_flipped_byte() {
+        let bytes = save(&octree(true));
+        for i in [0, 7, HEADER_SIZE + 5, bytes.len() / 2, bytes.len() - 1] {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x10;
+            assert!(CellOctree::load(corrupt.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = save(&octree(false));
+        for len in [0, 3, HEADER_PREFIX_SIZE + 4, HEADER_SIZE, bytes.len() - 1] {
+            let mut truncated = bytes[..len].to_vec();
+            assert!(CellOctree::load(truncated.as_slice()).is_err());
+            // Also with a valid checksum, so the lengths are checked.
+            if len >= 4 {
+                resign(&mut truncated);
+                assert!(CellOctree::load(truncated.as_slice()).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = save(&octree(false));
+        bytes[..4].copy_from_slice(b"ODCX");
+        resign(&mut bytes);
+        assert!(CellOctree::load(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        for version in [0, VERSION + 1, u16::MAX] {
+            let mut bytes = save(&octree(false));
+            bytes[4..6].copy_from_slice(&version.to_le_bytes());
+            resign(&mut bytes);
+            let error = CellOctree::load(bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_ids() {
+        let octree = octree(false);
+        let mut bytes = save(&octree);
+        let num_cells = octree.all_cells().len() as u32;
+        bytes[HEADER_SIZE - 8..HEADER_SIZE - 4].copy_from_slice(&num_cells.to_le_bytes());
+        resign(&mut bytes);
+        assert!(CellOctree::load(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{Cell, CellId, CellOctree, CellQefs, ContourWork, MaterialId};
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+
+/// Summary of a [`CellOctree`] for tuning build parameters and budgets.
+#[derive(Clone, Debug, Default)]
+pub struct OctreeStats {
+    /// Cell counts indexed by depth.
+    pub depths: Vec<DepthStats>,
+    /// Errors of the cells that [`CellOctree::dual_contour`] visits as leaves.
+    pub qef_error: QefErrorStats,
+    /// Allocated for [`CellOctree::all_cells`].
+    pub cell_bytes: usize,
+    /// Allocated for [`CellOctree::qefs`].
+    pub qef_bytes: usize,
+    /// Allocated for [`CellOctree::corner_materials`].
+    pub material_bytes: usize,
+    /// Allocated for the traversal stacks of [`CellOctree::dual_contour`].
+    pub stack_bytes: usize,
+    /// From the [`BuildReport`](crate::BuildReport) of the last build.
+    pub sdf_evaluations: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DepthStats {
+    /// Leaves at the maximum depth, which were never subdivided.
+    pub leaves: usize,
+    /// Branches that were simplified into leaves.
+    ///
+    /// [`CellOctree::compact`] removes the children of pseudo-leaves, after
+    /// which they count as `leaves`.
+    pub pseudo_leaves: usize,
+    pub branches: usize,
+    /// Descendants of pseudo-leaves, which are never visited.
+    pub orphans: usize,
+}
+
+impl DepthStats {
+    pub fn total(&self) -> usize {
+        self.leaves + self.pseudo_leaves + self.branches + self.orphans
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QefErrorStats {
+    pub count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub median: f32,
+    /// 90th percentile.
+    pub p90: f32,
+    /// 99th percentile.
+    pub p99: f32,
+}
+
+impl QefErrorStats {
+    fn from_errors(mut errors: Vec<f32>) -> Self {
+        if errors.is_empty() {
+            return Self::default();
+        }
+        errors.sort_unstable_by(f32::total_cmp);
+        let percentile = |p: f32| errors[((errors.len() - 1) as f32 * p).round() as usize];
+        Self {
+            count: errors.len(),
+            min: errors[0],
+            max: errors[errors.len() - 1],
+            mean: errors.iter().sum::<f32>() / errors.len() as f32,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+impl OctreeStats {
+    pub fn total_cells(&self) -> usize {
+        self.depths.iter().map(DepthStats::total).sum()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.cell_bytes + self.qef_bytes + self.material_bytes + self.stack_bytes
+    }
+
+    /// The shallowest and deepest depths of leaves and pseudo-leaves.
+    pub fn leaf_depth_range(&self) -> Option<RangeInclusive<u8>> {
+        let mut depths = self
+            .depths
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.leaves + d.pseudo_leaves > 0)
+            .map(|(depth, _)| depth as u8);
+        let min = depths.next()?;
+        let max = depths.next_back().unwrap_or(min);
+        Some(min..=max)
+    }
+}
+
+impl CellOctree {
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            cell_bytes: self.all_cells.capacity() * size_of::<Cell>(),
+            qef_bytes: self.qefs.capacity() * size_of::<CellQefs>(),
+            material_bytes: self.corner_materials.capacity() * size_of::<[MaterialId; 8]>(),
+            stack_bytes: self.cell_stack.capacity() * size_of::<CellId>()
+                + self.work_stack.capacity() * size_of::<ContourWork>(),
+            sdf_evaluations: self.build_report.as_ref().map(|r| r.sdf_evaluations),
+            ..Default::default()
+        };
+        if self.all_cells.is_empty() {
+            return stats;
+        }
+
+        let mut errors = Vec::new();
+        let mut stack = vec![(self.root_id, false)];
+        while let Some((cell_id, is_orphan)) = stack.pop() {
+            let cell = &self.all_cells[cell_id as usize];
+            let depth = cell.depth as usize;
+            if stats.depths.len() <= depth {
+                stats.depths.resize(depth + 1, DepthStats::default());
+            }
+            let has_children = cell.children.iter().any(Option::is_some);
+            let counts = &mut stats.depths[depth];
+            if is_orphan {
+                counts.orphans += 1;
+            } else if !cell.is_leaf {
+                counts.branches += 1;
+            } else if has_children {
+                counts.pseudo_leaves += 1;
+            } else {
+                counts.leaves += 1;
+            }
+            if cell.is_leaf && !is_orphan {
+                errors.push(cell.qef_error);
+            }
+            let children_are_orphans = is_orphan || cell.is_leaf;
+            stack.extend(
+                cell.children
+                    .iter()
+                    .flatten()
+                    .map(|&child| (child, children_are_orphans)),
+            );
+        }
+        stats.qef_error = QefErrorStats::from_errors(errors);
+
+        stats
+    }
+}